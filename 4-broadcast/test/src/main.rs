@@ -1,5 +1,7 @@
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::env;
+use std::fs::{File, OpenOptions};
 use std::io::Write;
 
 use clap::Parser;
@@ -7,11 +9,11 @@ use env_logger::Builder;
 use log::LevelFilter;
 use rand::prelude::*;
 use rand_pcg::Pcg64;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use sugars::{rc, refcell};
 
-use dslib::node::LocalEventType;
+use dslib::node::{Context, LocalEventType, Node};
 use dslib::pynode::{JsonMessage, PyNodeFactory};
 use dslib::system::System;
 use dslib::test::{TestResult, TestSuite};
@@ -23,12 +25,32 @@ struct Message<'a> {
     text: &'a str,
 }
 
+#[derive(Serialize)]
+struct JoinMessage<'a> {
+    seed: &'a str,
+}
+
+#[derive(Serialize)]
+struct LeaveMessage {}
+
+#[derive(Deserialize)]
+struct ViewMessage {
+    view: u64,
+    members: Vec<String>,
+}
+
 #[derive(Clone)]
 struct TestConfig {
     solution_path: String,
     node_count: u32,
     seed: u64,
     monkeys: u32,
+    faults: u32,
+    byzantine: u32,
+    fast_path: Option<u32>,
+    report: Option<String>,
+    dynamic: bool,
+    monitor: bool,
     debug: bool,
 }
 
@@ -48,12 +70,199 @@ fn build_system(config: &TestConfig) -> System<JsonMessage> {
     let node_factory = PyNodeFactory::new(&config.solution_path, "BroadcastNode");
     for node_id in node_ids.iter() {
         let node = node_factory.build(node_id, (node_id, node_ids.clone()), config.seed);
-        sys.add_node(rc!(refcell!(node)));
+        sys.add_node(rc!(refcell!(ObservedNode { node })));
     }
     return sys;
 }
 
-fn check(sys: System<JsonMessage>, config: &TestConfig) -> TestResult {
+// new node knows only about itself and should join the group via JOIN message
+fn add_joining_node(node_id: &str, sys: &mut System<JsonMessage>, config: &TestConfig) {
+    let node_factory = PyNodeFactory::new(&config.solution_path, "BroadcastNode");
+    let node = node_factory.build(node_id, (node_id, vec![node_id]), config.seed);
+    sys.add_node(rc!(refcell!(ObservedNode { node })));
+}
+
+fn byzantine_nodes(config: &TestConfig) -> HashSet<String> {
+    (config.node_count - config.byzantine..config.node_count)
+        .map(|n| n.to_string())
+        .collect()
+}
+
+fn build_byzantine_system(config: &TestConfig) -> System<JsonMessage> {
+    let mut sys = System::with_seed(config.seed);
+    let mut node_ids = Vec::new();
+    for n in 0..config.node_count {
+        node_ids.push(format!("{}", n));
+    }
+    let byzantine = byzantine_nodes(config);
+    let node_factory = PyNodeFactory::new(&config.solution_path, "BroadcastNode");
+    for node_id in node_ids.iter() {
+        if byzantine.contains(node_id) {
+            let node = ByzantineNode::new(node_id, node_ids.clone(), config.seed);
+            sys.add_node(rc!(refcell!(ObservedNode { node })));
+        } else {
+            let node = node_factory.build(node_id, (node_id, node_ids.clone()), config.seed);
+            sys.add_node(rc!(refcell!(ObservedNode { node })));
+        }
+    }
+    return sys;
+}
+
+// properties which can not be guaranteed when the majority of nodes fails
+const LIVENESS_PROPERTIES: [&str; 2] = ["VALIDITY", "UNIFORM AGREEMENT"];
+
+// number of chaos monkey iterations during which the victims can crash
+const CHAOS_MONKEY_STEPS: u32 = 90;
+
+// METRICS -----------------------------------------------------------------------------------------
+
+#[derive(Serialize)]
+struct MessageLatency {
+    text: String,
+    sent_at: f64,
+    // time from SEND to DELIVER at each correct node which delivered the message
+    delivery: Vec<f64>,
+    // time from SEND until the message is delivered at all correct nodes
+    stable: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct Distribution {
+    count: usize,
+    min: f64,
+    mean: f64,
+    p50: f64,
+    p90: f64,
+    p99: f64,
+    max: f64,
+}
+
+#[derive(Serialize)]
+struct LatencyReport<'a> {
+    seed: u64,
+    node_count: u32,
+    delivery: Option<Distribution>,
+    stable: Option<Distribution>,
+    unstable: usize,
+    messages: &'a Vec<MessageLatency>,
+}
+
+fn measure_latencies(sys: &System<JsonMessage>, correct: &[String]) -> Vec<MessageLatency> {
+    let mut sent_at = Vec::new();
+    let mut delivered_at = HashMap::new();
+    for node in sys.get_node_ids() {
+        for e in sys.get_local_events(&node) {
+            let m = e.msg.unwrap();
+            if m.tip != "SEND" && m.tip != "DELIVER" {
+                continue;
+            }
+            let data: Value = serde_json::from_str(&m.data).unwrap();
+            let message = data["text"].as_str().unwrap().to_string();
+            match e.tip {
+                LocalEventType::LocalMessageReceive => sent_at.push((message, e.time)),
+                LocalEventType::LocalMessageSend => {
+                    if correct.contains(&node) {
+                        delivered_at
+                            .entry(message)
+                            .or_insert_with(HashMap::new)
+                            .entry(node.clone())
+                            .or_insert(e.time);
+                    }
+                }
+            }
+        }
+    }
+    sent_at.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+
+    let mut latencies = Vec::new();
+    for (text, time) in sent_at {
+        let nodes = delivered_at.remove(&text).unwrap_or_default();
+        let delivery = nodes.values().map(|t| t - time).collect::<Vec<f64>>();
+        let stable = if correct.iter().all(|n| nodes.contains_key(n)) {
+            delivery.iter().cloned().reduce(f64::max)
+        } else {
+            None
+        };
+        latencies.push(MessageLatency {
+            text,
+            sent_at: time,
+            delivery,
+            stable,
+        });
+    }
+    latencies
+}
+
+fn distribution(values: &[f64]) -> Option<Distribution> {
+    if values.is_empty() {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let percentile = |p: f64| sorted[(p * (sorted.len() - 1) as f64).round() as usize];
+    Some(Distribution {
+        count: sorted.len(),
+        min: sorted[0],
+        mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+        p50: percentile(0.5),
+        p90: percentile(0.9),
+        p99: percentile(0.99),
+        max: sorted[sorted.len() - 1],
+    })
+}
+
+fn report_latencies(sys: &System<JsonMessage>, correct: &[String], config: &TestConfig) {
+    if !config.debug && config.report.is_none() {
+        return;
+    }
+    let latencies = measure_latencies(sys, correct);
+    let delivery = latencies
+        .iter()
+        .flat_map(|l| l.delivery.iter().cloned())
+        .collect::<Vec<f64>>();
+    let stable = latencies
+        .iter()
+        .filter_map(|l| l.stable)
+        .collect::<Vec<f64>>();
+    let report = LatencyReport {
+        seed: config.seed,
+        node_count: config.node_count,
+        delivery: distribution(&delivery),
+        stable: distribution(&stable),
+        unstable: latencies.len() - stable.len(),
+        messages: &latencies,
+    };
+
+    if config.debug {
+        for (name, dist) in [
+            ("Delivery", &report.delivery),
+            ("Stability", &report.stable),
+        ] {
+            if let Some(d) = dist {
+                println!(
+                    "{} latency: count - {}, min - {:.2}, mean - {:.2}, p50 - {:.2}, p90 - {:.2}, p99 - {:.2}, max - {:.2}",
+                    name, d.count, d.min, d.mean, d.p50, d.p90, d.p99, d.max
+                );
+            }
+        }
+        if report.unstable > 0 {
+            println!(
+                "Messages not delivered at all correct nodes: {}",
+                report.unstable
+            );
+        }
+    }
+    if let Some(path) = &config.report {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        writeln!(file, "{}", serde_json::to_string(&report).unwrap()).unwrap();
+    }
+}
+
+fn violated_properties(sys: System<JsonMessage>, config: &TestConfig) -> Vec<&'static str> {
     let mut sent = HashMap::new();
     let mut delivered = HashMap::new();
     let mut all_sent = HashSet::new();
@@ -102,6 +311,12 @@ fn check(sys: System<JsonMessage>, config: &TestConfig) -> TestResult {
             );
         }
     }
+    let correct = sys
+        .get_node_ids()
+        .into_iter()
+        .filter(|n| !sys.node_is_crashed(n))
+        .collect::<Vec<String>>();
+    report_latencies(&sys, &correct, config);
 
     // NO DUPLICATION
     let mut no_duplication = true;
@@ -202,7 +417,163 @@ fn check(sys: System<JsonMessage>, config: &TestConfig) -> TestResult {
         }
     }
 
-    if no_duplication & no_creation & validity & uniform_agreement & causal_order {
+    let mut violated = Vec::new();
+    if !no_duplication {
+        violated.push("NO DUPLICATION")
+    }
+    if !no_creation {
+        violated.push("NO CREATION")
+    }
+    if !validity {
+        violated.push("VALIDITY")
+    }
+    if !uniform_agreement {
+        violated.push("UNIFORM AGREEMENT")
+    }
+    if !causal_order {
+        violated.push("CAUSAL ORDER")
+    }
+    violated
+}
+
+fn check(sys: System<JsonMessage>, config: &TestConfig) -> TestResult {
+    let violated = violated_properties(sys, config);
+    if violated.is_empty() {
+        Ok(true)
+    } else {
+        Err(format!("Violated {}", violated.join(", ")))
+    }
+}
+
+// used when the majority of nodes is allowed to fail, so the liveness can not be guaranteed
+fn check_over_budget(sys: System<JsonMessage>, config: &TestConfig) -> TestResult {
+    let crashed = sys
+        .get_node_ids()
+        .into_iter()
+        .filter(|n| sys.node_is_crashed(n))
+        .count();
+    let (liveness, safety): (Vec<_>, Vec<_>) = violated_properties(sys, config)
+        .into_iter()
+        .partition(|p| LIVENESS_PROPERTIES.contains(p));
+    if !liveness.is_empty() {
+        println!(
+            "Violated {} (expected: {} of {} nodes crashed)",
+            liveness.join(", "),
+            crashed,
+            config.node_count
+        );
+    }
+    if safety.is_empty() {
+        Ok(true)
+    } else {
+        Err(format!("Violated {}", safety.join(", ")))
+    }
+}
+
+fn check_byzantine(sys: System<JsonMessage>, config: &TestConfig) -> TestResult {
+    let byzantine = byzantine_nodes(config);
+    let correct = sys
+        .get_node_ids()
+        .into_iter()
+        .filter(|n| !byzantine.contains(n))
+        .collect::<Vec<String>>();
+    let mut sent = HashMap::new();
+    let mut delivered = HashMap::new();
+    for node in correct.iter() {
+        let mut sent_msgs = Vec::new();
+        let mut delivered_msgs = Vec::new();
+        for e in sys.get_local_events(node) {
+            let m = e.msg.unwrap();
+            let data: Value = serde_json::from_str(&m.data).unwrap();
+            let message = data["text"].as_str().unwrap().to_string();
+            match e.tip {
+                LocalEventType::LocalMessageReceive => sent_msgs.push(message),
+                LocalEventType::LocalMessageSend => delivered_msgs.push(message),
+            }
+        }
+        sent.insert(node.clone(), sent_msgs);
+        delivered.insert(node.clone(), delivered_msgs);
+    }
+
+    if config.debug {
+        println!(
+            "Messages sent across network: {}",
+            sys.get_network_message_count()
+        );
+        println!(
+            "Byzantine nodes: {}",
+            byzantine.iter().cloned().collect::<Vec<_>>().join(", ")
+        );
+        println!("Correct node deliveries:");
+        for node in correct.iter() {
+            println!(
+                "- [node {}] {}",
+                node,
+                delivered.get(node).unwrap().join(", ")
+            );
+        }
+    }
+    report_latencies(&sys, &correct, config);
+
+    // NO DUPLICATION
+    let mut no_duplication = true;
+    for (node, delivered_msgs) in &delivered {
+        let mut uniq = HashSet::new();
+        for msg in delivered_msgs {
+            if !uniq.insert(msg) {
+                println!("Message {} is duplicated at node {}!", msg, node);
+                no_duplication = false;
+            }
+        }
+    }
+
+    // NO CREATION (messages of correct users cannot be forged, byzantine users may send anything)
+    let mut no_creation = true;
+    for (node, delivered_msgs) in &delivered {
+        for msg in delivered_msgs {
+            let author = msg.split(':').next().unwrap();
+            if byzantine.contains(author) {
+                continue;
+            }
+            let authentic = match sent.get(author) {
+                Some(sent_msgs) => sent_msgs.contains(msg),
+                None => false,
+            };
+            if !authentic {
+                println!(
+                    "Message {} was not sent by {}, but is delivered at node {}!",
+                    msg, author, node
+                );
+                no_creation = false;
+            }
+        }
+    }
+
+    // VALIDITY
+    let mut validity = true;
+    for (node, sent_msgs) in &sent {
+        let delivered_msgs = delivered.get(node).unwrap();
+        for msg in sent_msgs {
+            if !delivered_msgs.contains(msg) {
+                println!("Node {} has not delivered its own message {}!", node, msg);
+                validity = false;
+            }
+        }
+    }
+
+    // AGREEMENT (also rules out equivocation: correct nodes cannot deliver different versions)
+    let mut agreement = true;
+    let all_delivered = delivered.values().flatten().collect::<HashSet<_>>();
+    for msg in all_delivered {
+        for (node, delivered_msgs) in &delivered {
+            if !delivered_msgs.contains(msg) {
+                println!("Message {} is not delivered by correct node {}!", msg, node);
+                agreement = false;
+            }
+        }
+    }
+
+    if no_duplication & no_creation & validity & agreement {
         Ok(true)
     } else {
         let mut violated = Vec::new();
@@ -215,148 +586,881 @@ fn check(sys: System<JsonMessage>, config: &TestConfig) -> TestResult {
         if !validity {
             violated.push("VALIDITY")
         }
-        if !uniform_agreement {
-            violated.push("UNIFORM AGREEMENT")
+        if !agreement {
+            violated.push("AGREEMENT")
+        }
+        Err(format!("Violated {}", violated.join(", ")))
+    }
+}
+
+fn check_view_synchrony(
+    sys: System<JsonMessage>,
+    config: &TestConfig,
+    left: &[String],
+) -> TestResult {
+    let mut sent = HashMap::new();
+    // delivered messages and the views in which they were delivered (None is the initial view)
+    let mut delivered = HashMap::new();
+    // sequence of views installed by each node
+    let mut views = HashMap::new();
+    let mut view_members: HashMap<u64, HashSet<String>> = HashMap::new();
+    let mut histories = HashMap::new();
+    let mut view_agreement = true;
+    for node in sys.get_node_ids() {
+        let mut sent_msgs = Vec::new();
+        let mut delivered_msgs = Vec::new();
+        let mut history = Vec::new();
+        let initial = node.parse::<u32>().unwrap() < config.node_count;
+        let mut node_views = if initial { vec![None] } else { Vec::new() };
+        let mut view = None;
+        for e in sys.get_local_events(&node) {
+            let m = e.msg.unwrap();
+            match (e.tip, m.tip.as_str()) {
+                (LocalEventType::LocalMessageReceive, "SEND") => {
+                    let data: Value = serde_json::from_str(&m.data).unwrap();
+                    let message = data["text"].as_str().unwrap().to_string();
+                    sent_msgs.push(message.clone());
+                    history.push(message);
+                }
+                (LocalEventType::LocalMessageReceive, tip) => {
+                    history.push(tip.to_string());
+                }
+                (LocalEventType::LocalMessageSend, "VIEW") => {
+                    let data: ViewMessage = serde_json::from_str(&m.data).unwrap();
+                    let members = data.members.into_iter().collect::<HashSet<String>>();
+                    if !members.contains(&node) {
+                        println!("Node {} installed view {} without itself!", node, data.view);
+                        view_agreement = false;
+                    }
+                    match view_members.get(&data.view) {
+                        Some(known) if *known != members => {
+                            println!(
+                                "Node {} installed view {} with different members!",
+                                node, data.view
+                            );
+                            view_agreement = false;
+                        }
+                        _ => {
+                            view_members.insert(data.view, members);
+                        }
+                    }
+                    view = Some(data.view);
+                    node_views.push(view);
+                    history.push(format!("<view {}>", data.view));
+                }
+                (LocalEventType::LocalMessageSend, _) => {
+                    let data: Value = serde_json::from_str(&m.data).unwrap();
+                    let message = data["text"].as_str().unwrap().to_string();
+                    delivered_msgs.push((message.clone(), view));
+                    history.push(message);
+                }
+            }
+        }
+        sent.insert(node.clone(), sent_msgs);
+        delivered.insert(node.clone(), delivered_msgs);
+        views.insert(node.clone(), node_views);
+        histories.insert(node, history);
+    }
+    let survivors = sys
+        .get_node_ids()
+        .into_iter()
+        .filter(|n| !sys.node_is_crashed(n) && !left.contains(n))
+        .collect::<Vec<String>>();
+
+    if config.debug {
+        println!(
+            "Messages sent across network: {}",
+            sys.get_network_message_count()
+        );
+        println!("Node histories:");
+        for node in sys.get_node_ids() {
+            println!(
+                "- [node {}] {}",
+                node,
+                histories.get(&node).unwrap().join(", ")
+            );
+        }
+    }
+    report_latencies(&sys, &survivors, config);
+
+    // NO DUPLICATION
+    let mut no_duplication = true;
+    for (node, delivered_msgs) in &delivered {
+        let mut uniq = HashSet::new();
+        for (msg, _) in delivered_msgs {
+            if !uniq.insert(msg) {
+                println!("Message {} is duplicated at node {}!", msg, node);
+                no_duplication = false;
+            }
+        }
+    }
+
+    // NO CREATION
+    let mut no_creation = true;
+    let all_sent = sent.values().flatten().collect::<HashSet<_>>();
+    for delivered_msgs in delivered.values() {
+        for (msg, _) in delivered_msgs {
+            if !all_sent.contains(msg) {
+                println!("Message {} was not sent!", msg);
+                no_creation = false;
+            }
+        }
+    }
+
+    // VALIDITY
+    let mut validity = true;
+    for node in survivors.iter() {
+        let delivered_msgs = delivered.get(node).unwrap();
+        for msg in sent.get(node).unwrap() {
+            if !delivered_msgs.iter().any(|(m, _)| m == msg) {
+                println!("Node {} has not delivered its own message {}!", node, msg);
+                validity = false;
+            }
+        }
+    }
+
+    // SAME VIEW DELIVERY
+    let mut same_view_delivery = true;
+    let mut delivery_views = HashMap::new();
+    for (node, delivered_msgs) in &delivered {
+        for (msg, view) in delivered_msgs {
+            match delivery_views.get(msg) {
+                Some((other, other_view)) if other_view != view => {
+                    println!(
+                        "Message {} is delivered in view {:?} at node {} and in view {:?} at node {}!",
+                        msg, view, node, other_view, other
+                    );
+                    same_view_delivery = false;
+                }
+                Some(_) => {}
+                None => {
+                    delivery_views.insert(msg, (node, *view));
+                }
+            }
+        }
+    }
+
+    // VIEW SYNCHRONY (nodes which move from view v to the same next view deliver the same messages in v)
+    let mut view_synchrony = true;
+    let nodes = sys.get_node_ids();
+    for (i, node1) in nodes.iter().enumerate() {
+        for node2 in nodes.iter().skip(i + 1) {
+            let views1 = views.get(node1).unwrap();
+            let views2 = views.get(node2).unwrap();
+            for pair in views1.windows(2) {
+                if !views2.windows(2).any(|p| p == pair) {
+                    continue;
+                }
+                let in_view = |node: &String| {
+                    delivered
+                        .get(node)
+                        .unwrap()
+                        .iter()
+                        .filter(|(_, v)| *v == pair[0])
+                        .map(|(m, _)| m.clone())
+                        .collect::<HashSet<String>>()
+                };
+                let (msgs1, msgs2) = (in_view(node1), in_view(node2));
+                if msgs1 != msgs2 {
+                    let mut diff = msgs1
+                        .symmetric_difference(&msgs2)
+                        .cloned()
+                        .collect::<Vec<String>>();
+                    diff.sort();
+                    println!(
+                        "Nodes {} and {} moved from view {:?} to {:?}, but delivered different messages in it: [{}]",
+                        node1, node2, pair[0], pair[1], diff.join(", ")
+                    );
+                    view_synchrony = false;
+                }
+            }
+        }
+    }
+
+    // FINAL VIEW (all surviving nodes end up in the same view without the nodes which left)
+    let mut final_view = true;
+    let last_views = survivors
+        .iter()
+        .map(|n| *views.get(n).unwrap().last().unwrap_or(&None))
+        .collect::<HashSet<Option<u64>>>();
+    if last_views.len() != 1 {
+        println!(
+            "Surviving nodes ended up in different views: {:?}",
+            last_views
+        );
+        final_view = false;
+    } else if let Some(Some(view)) = last_views.iter().next() {
+        let members = view_members.get(view).unwrap();
+        for node in survivors.iter() {
+            if !members.contains(node) {
+                println!("Node {} is missing from the final view {}!", node, view);
+                final_view = false;
+            }
         }
-        if !causal_order {
-            violated.push("CAUSAL ORDER")
+        for node in left.iter() {
+            if members.contains(node) {
+                println!(
+                    "Node {} which left is still in the final view {}!",
+                    node, view
+                );
+                final_view = false;
+            }
         }
+    } else if !left.is_empty()
+        || survivors
+            .iter()
+            .any(|n| n.parse::<u32>().unwrap() >= config.node_count)
+    {
+        println!("Surviving nodes have not installed any new view!");
+        final_view = false;
+    }
+
+    let mut violated = Vec::new();
+    if !no_duplication {
+        violated.push("NO DUPLICATION")
+    }
+    if !no_creation {
+        violated.push("NO CREATION")
+    }
+    if !validity {
+        violated.push("VALIDITY")
+    }
+    if !view_agreement {
+        violated.push("VIEW AGREEMENT")
+    }
+    if !same_view_delivery {
+        violated.push("SAME VIEW DELIVERY")
+    }
+    if !view_synchrony {
+        violated.push("VIEW SYNCHRONY")
+    }
+    if !final_view {
+        violated.push("FINAL VIEW")
+    }
+    if violated.is_empty() {
+        Ok(true)
+    } else {
         Err(format!("Violated {}", violated.join(", ")))
     }
 }
 
+// MONITORS ----------------------------------------------------------------------------------------
+
+thread_local! {
+    // nodes which have processed some events since the last observation of the monitor
+    static ACTIVE_NODES: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+// node wrapper which marks the node as active when it processes an event
+struct ObservedNode<N> {
+    node: N,
+}
+
+impl<N> ObservedNode<N> {
+    fn mark_active(id: &str) {
+        ACTIVE_NODES.with(|nodes| nodes.borrow_mut().insert(id.to_string()));
+    }
+}
+
+impl<N: Node<JsonMessage>> Node<JsonMessage> for ObservedNode<N> {
+    fn id(&self) -> &String {
+        self.node.id()
+    }
+
+    fn on_message(&mut self, msg: JsonMessage, from: String, ctx: &mut Context<JsonMessage>) {
+        Self::mark_active(self.node.id());
+        self.node.on_message(msg, from, ctx);
+    }
+
+    fn on_local_message(&mut self, msg: JsonMessage, ctx: &mut Context<JsonMessage>) {
+        Self::mark_active(self.node.id());
+        self.node.on_local_message(msg, ctx);
+    }
+
+    fn on_timer(&mut self, timer: String, ctx: &mut Context<JsonMessage>) {
+        Self::mark_active(self.node.id());
+        self.node.on_timer(timer, ctx);
+    }
+
+    fn max_size(&mut self) -> u64 {
+        self.node.max_size()
+    }
+}
+
+// checks safety properties after every step to stop at the exact violating event
+struct Monitor {
+    enabled: bool,
+    step: u64,
+    // number of already checked local events per node
+    checked: HashMap<String, usize>,
+    sent: HashSet<String>,
+    histories: HashMap<String, Vec<String>>,
+    delivered: HashMap<String, HashSet<String>>,
+    // events of the sender which happened before the message was sent
+    pasts: HashMap<String, HashSet<String>>,
+    // first causal order violation by each node (step, time and offending event)
+    causal_violations: HashMap<String, (u64, f64, String)>,
+}
+
+impl Monitor {
+    fn new(config: &TestConfig) -> Self {
+        ACTIVE_NODES.with(|nodes| nodes.borrow_mut().clear());
+        Self {
+            enabled: config.monitor,
+            step: 0,
+            checked: HashMap::new(),
+            sent: HashSet::new(),
+            histories: HashMap::new(),
+            delivered: HashMap::new(),
+            pasts: HashMap::new(),
+            causal_violations: HashMap::new(),
+        }
+    }
+
+    fn step(&mut self, sys: &mut System<JsonMessage>) -> Result<bool, String> {
+        let res = sys.step();
+        if self.enabled {
+            self.step += 1;
+            self.observe(sys)?;
+        }
+        Ok(res)
+    }
+
+    fn steps(&mut self, sys: &mut System<JsonMessage>, step_count: u32) -> Result<bool, String> {
+        for _ in 0..step_count {
+            if !self.step(sys)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn step_until_no_events(&mut self, sys: &mut System<JsonMessage>) -> Result<(), String> {
+        while self.step(sys)? {}
+        let mut violations = self
+            .causal_violations
+            .iter()
+            .filter(|(node, _)| !sys.node_is_crashed(node))
+            .map(|(_, violation)| violation)
+            .collect::<Vec<_>>();
+        violations.sort_by_key(|(step, _, _)| *step);
+        if let Some((step, time, event)) = violations.first() {
+            println!("Step {}, time {:.3}: {}", step, time, event);
+            return Err(format!("Violated CAUSAL ORDER at step {}", step));
+        }
+        Ok(())
+    }
+
+    fn observe(&mut self, sys: &System<JsonMessage>) -> Result<(), String> {
+        // only nodes which have processed some events can have new local events
+        let mut active = ACTIVE_NODES.with(|nodes| nodes.borrow_mut().drain().collect::<Vec<_>>());
+        active.sort();
+        let mut events = Vec::new();
+        for node in active {
+            let node_events = sys.get_local_events(&node);
+            let checked = self
+                .checked
+                .insert(node.clone(), node_events.len())
+                .unwrap_or(0);
+            events.extend(
+                node_events
+                    .into_iter()
+                    .skip(checked)
+                    .map(|e| (node.clone(), e)),
+            );
+        }
+        // events of different nodes are processed in the order of their time
+        events.sort_by(|(_, a), (_, b)| a.time.partial_cmp(&b.time).unwrap());
+        for (node, e) in events {
+            let m = e.msg.unwrap();
+            if m.tip != "SEND" && m.tip != "DELIVER" {
+                continue;
+            }
+            let data: Value = serde_json::from_str(&m.data).unwrap();
+            let message = data["text"].as_str().unwrap().to_string();
+            let history = self.histories.entry(node.clone()).or_default();
+            match e.tip {
+                LocalEventType::LocalMessageReceive => {
+                    self.sent.insert(message.clone());
+                    self.pasts
+                        .insert(message.clone(), history.iter().cloned().collect());
+                }
+                LocalEventType::LocalMessageSend => {
+                    let delivered = self.delivered.entry(node.clone()).or_default();
+                    let violation = if delivered.contains(&message) {
+                        Some("NO DUPLICATION")
+                    } else if !self.sent.contains(&message) {
+                        Some("NO CREATION")
+                    } else {
+                        None
+                    };
+                    if let Some(property) = violation {
+                        println!(
+                            "Step {}, time {:.3}: node {} delivered {}",
+                            self.step, e.time, node, message
+                        );
+                        return Err(format!("Violated {} at step {}", property, self.step));
+                    }
+                    let mut missing = self
+                        .pasts
+                        .get(&message)
+                        .unwrap()
+                        .difference(delivered)
+                        .cloned()
+                        .collect::<Vec<String>>();
+                    if !missing.is_empty() {
+                        missing.sort();
+                        let event = format!(
+                            "node {} delivered {} before delivering [{}]",
+                            node,
+                            message,
+                            missing.join(", ")
+                        );
+                        self.causal_violations
+                            .entry(node.clone())
+                            .or_insert((self.step, e.time, event));
+                    }
+                    delivered.insert(message.clone());
+                }
+            }
+            history.push(message);
+        }
+        Ok(())
+    }
+}
+
+// BYZANTINE NODE ----------------------------------------------------------------------------------
+
+// fields which solutions commonly use to store the original sender of a message
+const SENDER_FIELDS: [&str; 7] = [
+    "from", "sender", "src", "source", "origin", "author", "user",
+];
+
+// total number of messages each adversary is allowed to send (guarantees that the test terminates)
+const BYZANTINE_BUDGET: u32 = 200;
+
+// adversary node which forges, replays and equivocates copies of the received messages
+struct ByzantineNode {
+    id: String,
+    peers: Vec<String>,
+    rand: Pcg64,
+    seen: Vec<JsonMessage>,
+    forged: u32,
+    budget: u32,
+}
+
+impl ByzantineNode {
+    fn new(id: &str, node_ids: Vec<String>, seed: u64) -> Self {
+        let peers = node_ids.into_iter().filter(|n| n != id).collect();
+        Self {
+            id: id.to_string(),
+            peers,
+            rand: Pcg64::seed_from_u64(seed),
+            seen: Vec::new(),
+            forged: 0,
+            budget: BYZANTINE_BUDGET,
+        }
+    }
+
+    fn is_text(&self, value: &str) -> bool {
+        match value.split_once(':') {
+            Some((user, _)) => user == self.id || self.peers.iter().any(|n| n == user),
+            None => false,
+        }
+    }
+
+    fn forge(&self, value: &Value, author: &str, text: &str) -> Value {
+        match value {
+            Value::String(s) if self.is_text(s) => Value::String(text.to_string()),
+            Value::Array(items) => Value::Array(
+                items
+                    .iter()
+                    .map(|item| self.forge(item, author, text))
+                    .collect(),
+            ),
+            Value::Object(fields) => Value::Object(
+                fields
+                    .iter()
+                    .map(|(k, v)| {
+                        let forged = match v {
+                            Value::String(_) if SENDER_FIELDS.contains(&k.as_str()) => {
+                                Value::String(author.to_string())
+                            }
+                            _ => self.forge(v, author, text),
+                        };
+                        (k.clone(), forged)
+                    })
+                    .collect(),
+            ),
+            _ => value.clone(),
+        }
+    }
+
+    fn send(&mut self, msg: JsonMessage, to: &str, ctx: &mut Context<JsonMessage>) {
+        if self.budget > 0 {
+            self.budget -= 1;
+            ctx.send(msg, to);
+        }
+    }
+}
+
+impl Node<JsonMessage> for ByzantineNode {
+    fn id(&self) -> &String {
+        &self.id
+    }
+
+    fn on_message(&mut self, msg: JsonMessage, _from: String, ctx: &mut Context<JsonMessage>) {
+        if self.budget == 0 {
+            return;
+        }
+        self.seen.push(msg.clone());
+        let data: Value = match serde_json::from_str(&msg.data) {
+            Ok(data) => data,
+            Err(_) => return,
+        };
+        self.forged += 1;
+        // forge a message on behalf of some correct user and a message on behalf of itself,
+        // sending a different version of each to every peer
+        let victim = self.peers.choose(&mut self.rand).unwrap().clone();
+        let authors = [victim, self.id.clone()];
+        let peers = self.peers.clone();
+        for author in authors.iter() {
+            for peer in peers.iter() {
+                let text = format!("{}:forged-{}-{}", author, self.forged, peer);
+                let forged = self.forge(&data, author, &text);
+                self.send(JsonMessage::from(&msg.tip, &forged), peer, ctx);
+            }
+        }
+        // replay some old message
+        if self.rand.gen_range(0.0..1.0) < 0.5 {
+            let old = self.seen.choose(&mut self.rand).unwrap().clone();
+            let peer = self.peers.choose(&mut self.rand).unwrap().clone();
+            self.send(old, &peer, ctx);
+        }
+    }
+
+    fn on_local_message(&mut self, _msg: JsonMessage, _ctx: &mut Context<JsonMessage>) {}
+
+    fn on_timer(&mut self, _timer: String, _ctx: &mut Context<JsonMessage>) {}
+}
+
 // TESTS -------------------------------------------------------------------------------------------
 
 fn test_normal(config: &TestConfig) -> TestResult {
     let mut sys = build_system(config);
+    let mut monitor = Monitor::new(config);
     sys.send_local(JsonMessage::from("SEND", &Message { text: "0:Hello" }), "0");
-    sys.step_until_no_events();
+    monitor.step_until_no_events(&mut sys)?;
     check(sys, config)
 }
 
 fn test_sender_crash(config: &TestConfig) -> TestResult {
     let mut sys = build_system(config);
+    let mut monitor = Monitor::new(config);
     sys.send_local(JsonMessage::from("SEND", &Message { text: "0:Hello" }), "0");
     // let 2 messages to deliver (sender and one other node)
-    sys.step();
+    monitor.step(&mut sys)?;
     if sys.get_local_events("0").len() == 1 {
-        sys.steps(2);
+        monitor.steps(&mut sys, 2)?;
     } else {
-        sys.step();
+        monitor.step(&mut sys)?;
     }
     // crash source node
     sys.crash_node("0");
-    sys.step_until_no_events();
+    monitor.step_until_no_events(&mut sys)?;
     check(sys, config)
 }
 
 fn test_sender_crash2(config: &TestConfig) -> TestResult {
     let mut sys = build_system(config);
+    let mut monitor = Monitor::new(config);
     sys.send_local(JsonMessage::from("SEND", &Message { text: "0:Hello" }), "0");
     // let 1 message to deliver (sender only)
-    sys.step();
+    monitor.step(&mut sys)?;
     if sys.get_local_events("0").len() == 1 {
-        sys.step();
+        monitor.step(&mut sys)?;
     }
     sys.crash_node("0");
-    sys.step_until_no_events();
+    monitor.step_until_no_events(&mut sys)?;
     check(sys, config)
 }
 
 fn test_two_crashes(config: &TestConfig) -> TestResult {
     let mut sys = build_system(config);
+    let mut monitor = Monitor::new(config);
     sys.send_local(JsonMessage::from("SEND", &Message { text: "0:Hello" }), "0");
     // simulate that 0 and 1 communicated only with each other and then crashed
     for n in 2..config.node_count {
         sys.disconnect_node(&n.to_string());
     }
-    sys.steps(config.node_count.pow(2));
+    monitor.steps(&mut sys, config.node_count.pow(2))?;
     sys.crash_node("0");
     sys.crash_node("1");
-    sys.step_until_no_events();
+    monitor.step_until_no_events(&mut sys)?;
     check(sys, config)
 }
 
 fn test_two_crashes2(config: &TestConfig) -> TestResult {
     let mut sys = build_system(config);
+    let mut monitor = Monitor::new(config);
     sys.send_local(JsonMessage::from("SEND", &Message { text: "0:Hello" }), "0");
     // simulate that 1 and 2 communicated only with 0 and then crashed
     sys.drop_outgoing("1");
     sys.drop_outgoing("2");
-    sys.steps(config.node_count.pow(2));
+    monitor.steps(&mut sys, config.node_count.pow(2))?;
     sys.crash_node("1");
     sys.crash_node("2");
-    sys.step_until_no_events();
+    monitor.step_until_no_events(&mut sys)?;
     check(sys, config)
 }
 
 fn test_causal_order(config: &TestConfig) -> TestResult {
     let mut sys = build_system(config);
+    let mut monitor = Monitor::new(config);
     sys.set_delays(100., 200.);
     sys.send_local(
         JsonMessage::from("SEND", &Message { text: "0:Hello!" }),
         "0",
     );
     while sys.get_local_events("1").len() == 0 {
-        sys.step();
+        monitor.step(&mut sys)?;
     }
     sys.set_delays(10., 20.);
     sys.send_local(JsonMessage::from("SEND", &Message { text: "1:How?" }), "1");
     while sys.get_local_events("0").len() < 3 {
-        sys.step();
+        monitor.step(&mut sys)?;
     }
     sys.set_delay(1.);
     sys.send_local(JsonMessage::from("SEND", &Message { text: "0:Fine!" }), "0");
-    sys.step_until_no_events();
+    monitor.step_until_no_events(&mut sys)?;
     check(sys, config)
 }
 
-fn test_chaos_monkey(config: &TestConfig) -> TestResult {
+fn chaos_monkey(
+    config: &TestConfig,
+    faults: u32,
+    check: fn(System<JsonMessage>, &TestConfig) -> TestResult,
+) -> TestResult {
     let mut rand = Pcg64::seed_from_u64(config.seed);
     for i in 1..=config.monkeys {
         let mut run_config = config.clone();
         run_config.seed = rand.next_u64();
         println!("- Run {} (seed: {})", i, run_config.seed);
         let mut sys = build_system(config);
-        let victim1 = rand.gen_range(0..config.node_count).to_string();
-        let mut victim2 = rand.gen_range(0..config.node_count).to_string();
-        while victim2 == victim1 {
-            victim2 = rand.gen_range(0..config.node_count).to_string();
-        }
+        let mut monitor = Monitor::new(config);
+        let victims = (0..config.node_count)
+            .map(|n| n.to_string())
+            .choose_multiple(&mut rand, faults as usize);
+        // each victim crashes at random step, possibly in the middle of message delivery
+        let crash_steps = victims
+            .iter()
+            .map(|_| rand.gen_range(0..=CHAOS_MONKEY_STEPS))
+            .collect::<Vec<u32>>();
+        let mut partitioned: Vec<String> = Vec::new();
+        let mut step = 0;
         for i in 0..10 {
-            let user = rand.gen_range(0..config.node_count).to_string();
+            let users = sys
+                .get_node_ids()
+                .into_iter()
+                .filter(|n| !sys.node_is_crashed(n))
+                .collect::<Vec<String>>();
+            let user = users.choose(&mut rand).unwrap();
             let message = format!("{}:{}", user, i);
-            sys.send_local(
-                JsonMessage::from("SEND", &Message { text: &message }),
-                &user,
-            );
+            sys.send_local(JsonMessage::from("SEND", &Message { text: &message }), user);
             if i % 2 == 0 {
                 sys.set_delays(10., 20.);
             } else {
                 sys.set_delays(1., 2.);
             }
-            for _ in 1..10 {
-                if rand.gen_range(0.0..1.0) > 0.3 {
-                    sys.drop_outgoing(&victim1);
-                } else {
-                    sys.pass_outgoing(&victim1);
+            // temporarily partition some of the victims from the rest of the system
+            for node in partitioned.drain(..) {
+                sys.connect_node(&node);
+            }
+            if rand.gen_range(0.0..1.0) < 0.3 {
+                let alive = victims
+                    .iter()
+                    .filter(|v| !sys.node_is_crashed(v))
+                    .cloned()
+                    .collect::<Vec<String>>();
+                if !alive.is_empty() {
+                    let count = rand.gen_range(1..=alive.len());
+                    partitioned = alive.choose_multiple(&mut rand, count).cloned().collect();
+                    for node in partitioned.iter() {
+                        sys.disconnect_node(node);
+                    }
                 }
-                if rand.gen_range(0.0..1.0) > 0.3 {
-                    sys.drop_outgoing(&victim2);
-                } else {
-                    sys.pass_outgoing(&victim2);
+            }
+            for _ in 1..10 {
+                for (victim, crash_step) in victims.iter().zip(crash_steps.iter()) {
+                    if sys.node_is_crashed(victim) {
+                        continue;
+                    }
+                    if step >= *crash_step {
+                        sys.crash_node(victim);
+                    } else if !partitioned.contains(victim) {
+                        if rand.gen_range(0.0..1.0) > 0.3 {
+                            sys.drop_outgoing(victim);
+                        } else {
+                            sys.pass_outgoing(victim);
+                        }
+                    }
                 }
-                sys.steps(rand.gen_range(1..5));
+                monitor.steps(&mut sys, rand.gen_range(1..5))?;
+                step += 1;
             }
         }
-        sys.crash_node(&victim1);
-        sys.crash_node(&victim2);
-        sys.step_until_no_events();
+        for victim in victims.iter() {
+            if !sys.node_is_crashed(victim) {
+                sys.crash_node(victim);
+            }
+        }
+        monitor.step_until_no_events(&mut sys)?;
         check(sys, config)?;
     }
     Ok(true)
 }
 
+fn test_chaos_monkey(config: &TestConfig) -> TestResult {
+    // number of faults is limited to f < N/2 for small N
+    let faults = config.faults.min(config.node_count.saturating_sub(1) / 2);
+    chaos_monkey(config, faults, check)
+}
+
+fn test_chaos_monkey_over_budget(config: &TestConfig) -> TestResult {
+    // correct nodes no longer form a majority
+    let faults = config.node_count - config.node_count / 2;
+    chaos_monkey(config, faults, check_over_budget)
+}
+
+fn view_change(config: &TestConfig, joins: u32, leaves: u32, crashes: u32) -> TestResult {
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut sys = build_system(config);
+    let mut members = sys.get_node_ids();
+    let mut joining = Vec::new();
+    for n in 0..joins {
+        let node_id = (config.node_count + n).to_string();
+        add_joining_node(&node_id, &mut sys, config);
+        joining.push(node_id);
+    }
+    let mut events = Vec::new();
+    events.extend((0..joins).map(|_| "JOIN"));
+    events.extend((0..leaves).map(|_| "LEAVE"));
+    events.extend((0..crashes).map(|_| "CRASH"));
+    events.shuffle(&mut rand);
+
+    // membership changes happen while broadcasts are in flight
+    let mut left = Vec::new();
+    for i in 0..10 + 2 * events.len() {
+        if i % 2 == 1 && !events.is_empty() {
+            match events.pop().unwrap() {
+                "JOIN" => {
+                    let node = joining.pop().unwrap();
+                    let seed = members.choose(&mut rand).unwrap();
+                    sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), &node);
+                    members.push(node);
+                }
+                "LEAVE" => {
+                    let node = members.remove(rand.gen_range(0..members.len()));
+                    sys.send_local(JsonMessage::from("LEAVE", &LeaveMessage {}), &node);
+                    left.push(node);
+                }
+                _ => {
+                    let node = members.remove(rand.gen_range(0..members.len()));
+                    sys.crash_node(&node);
+                }
+            }
+        }
+        let user = members.choose(&mut rand).unwrap();
+        let message = format!("{}:{}", user, i);
+        sys.send_local(JsonMessage::from("SEND", &Message { text: &message }), user);
+        sys.steps(rand.gen_range(1..5));
+    }
+    sys.step_until_no_events();
+    check_view_synchrony(sys, config, &left)
+}
+
+fn test_view_change_join(config: &TestConfig) -> TestResult {
+    view_change(config, 1, 0, 0)
+}
+
+fn test_view_change_leave(config: &TestConfig) -> TestResult {
+    view_change(config, 0, 1, 0)
+}
+
+fn test_view_change_churn(config: &TestConfig) -> TestResult {
+    view_change(config, 2, 1, 1)
+}
+
+fn test_fast_path(config: &TestConfig) -> TestResult {
+    let max_delays = config.fast_path.unwrap();
+    let mut sys = build_system(config);
+    let mut monitor = Monitor::new(config);
+    // every message takes exactly one network delay
+    sys.set_delay(1.);
+    for round in 0..2 {
+        for n in 0..config.node_count {
+            let user = n.to_string();
+            let message = format!("{}:Fast {}", user, round);
+            sys.send_local(
+                JsonMessage::from("SEND", &Message { text: &message }),
+                &user,
+            );
+        }
+        monitor.step_until_no_events(&mut sys)?;
+    }
+    let mut fast = true;
+    for latency in measure_latencies(&sys, &sys.get_node_ids()) {
+        match latency.stable {
+            Some(t) if t <= max_delays as f64 => {}
+            Some(t) => {
+                println!(
+                    "Message {} is delivered at all nodes after {:.2} network delays (max {})",
+                    latency.text, t, max_delays
+                );
+                fast = false;
+            }
+            None => {
+                println!("Message {} is not delivered at all nodes", latency.text);
+                fast = false;
+            }
+        }
+    }
+    check(sys, config)?;
+    if fast {
+        Ok(true)
+    } else {
+        Err(format!(
+            "Delivery takes more than {} network delays",
+            max_delays
+        ))
+    }
+}
+
+fn test_byzantine(config: &TestConfig) -> TestResult {
+    if config.node_count <= 3 * config.byzantine {
+        return Err(format!(
+            "Byzantine broadcast requires N > 3f (N = {}, f = {})",
+            config.node_count, config.byzantine
+        ));
+    }
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut sys = build_byzantine_system(config);
+    let byzantine = byzantine_nodes(config);
+    let correct = sys
+        .get_node_ids()
+        .into_iter()
+        .filter(|n| !byzantine.contains(n))
+        .collect::<Vec<String>>();
+    for i in 0..10 {
+        let user = correct.choose(&mut rand).unwrap();
+        let message = format!("{}:{}", user, i);
+        sys.send_local(JsonMessage::from("SEND", &Message { text: &message }), user);
+        sys.steps(rand.gen_range(1..10));
+    }
+    sys.step_until_no_events();
+    check_byzantine(sys, config)
+}
+
 #[allow(dead_code)]
 fn test_scalability(config: &TestConfig) -> TestResult {
     let sys_sizes = [
@@ -418,6 +1522,35 @@ struct Args {
     #[clap(long, short, default_value = "10")]
     monkeys: u32,
 
+    /// Number of crashed nodes in chaos monkey runs (reduced to the max f < N/2 if needed)
+    #[clap(long, short, default_value = "2")]
+    faults: u32,
+
+    /// Enable chaos monkey test with the majority of nodes crashed
+    #[clap(long)]
+    over_budget: bool,
+
+    /// Number of byzantine nodes (enables BYZANTINE test)
+    #[clap(long, short, default_value = "0")]
+    byzantine: u32,
+
+    /// Max number of network delays for delivering a message at all nodes
+    /// when there are no failures (enables FAST PATH test)
+    #[clap(long)]
+    fast_path: Option<u32>,
+
+    /// Path to JSON file for writing delivery latency report (one line per check)
+    #[clap(long, short)]
+    report: Option<String>,
+
+    /// Enable tests with nodes joining and leaving the group (requires JOIN/LEAVE/VIEW support)
+    #[clap(long)]
+    dynamic: bool,
+
+    /// Check safety properties after every step and stop at the first violation
+    #[clap(long)]
+    monitor: bool,
+
     /// Path to dslib directory
     #[clap(long = "lib", short = 'l', default_value = "../../dslib")]
     dslib_path: String,
@@ -436,8 +1569,17 @@ fn main() {
         node_count: args.node_count,
         seed: args.seed,
         monkeys: args.monkeys,
+        faults: args.faults,
+        byzantine: args.byzantine,
+        fast_path: args.fast_path,
+        report: args.report,
+        dynamic: args.dynamic,
+        monitor: args.monitor,
         debug: args.debug,
     };
+    if let Some(path) = &config.report {
+        File::create(path).unwrap();
+    }
     let mut tests = TestSuite::new();
 
     tests.add("NORMAL", test_normal, config.clone());
@@ -447,6 +1589,24 @@ fn main() {
     tests.add("TWO CRASHES 2", test_two_crashes2, config.clone());
     tests.add("CAUSAL ORDER", test_causal_order, config.clone());
     tests.add("CHAOS MONKEY", test_chaos_monkey, config.clone());
+    if args.over_budget {
+        tests.add(
+            "CHAOS MONKEY OVER BUDGET",
+            test_chaos_monkey_over_budget,
+            config.clone(),
+        );
+    }
+    if config.dynamic {
+        tests.add("VIEW CHANGE JOIN", test_view_change_join, config.clone());
+        tests.add("VIEW CHANGE LEAVE", test_view_change_leave, config.clone());
+        tests.add("VIEW CHANGE CHURN", test_view_change_churn, config.clone());
+    }
+    if config.fast_path.is_some() {
+        tests.add("FAST PATH", test_fast_path, config.clone());
+    }
+    if config.byzantine > 0 {
+        tests.add("BYZANTINE", test_byzantine, config.clone());
+    }
     tests.add("SCALABILITY", test_scalability, config.clone());
 
     if args.test.is_none() {
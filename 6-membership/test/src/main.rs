@@ -1,3 +1,4 @@
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::env;
 use std::io::Write;
@@ -11,6 +12,7 @@ use rand_pcg::Pcg64;
 use serde::{Deserialize, Serialize};
 use sugars::{rc, refcell};
 
+use dslib::node::LocalEventType;
 use dslib::pynode::{JsonMessage, PyNodeFactory};
 use dslib::system::System;
 use dslib::test::{TestResult, TestSuite};
@@ -33,13 +35,35 @@ struct MembersMessage {
     members: Vec<String>,
 }
 
+#[derive(Deserialize)]
+struct MemberChangeMessage {
+    member: String,
+}
+
 #[derive(Clone)]
 struct TestConfig<'a> {
     node_factory: &'a PyNodeFactory,
     node_count: u32,
     seed: u64,
+    max_detection_time: Option<f64>,
+    max_dissemination_time: Option<f64>,
+    max_false_positives: Option<u32>,
+    churn_rate: f64,
+    churn_duration: f64,
+    min_accuracy: f64,
+    large_scale: Option<u32>,
+    max_message_rate: Option<f64>,
+    max_flips: u32,
+    timeseries: Option<String>,
+    partial_view: Option<u32>,
 }
 
+// local messages emitted by nodes on membership changes (optional)
+const NOTIFICATION_TYPES: [&str; 3] = ["MEMBER_JOINED", "MEMBER_LEFT", "MEMBER_FAILED"];
+
+// interval between polls of members lists in failure detector measurements
+const POLL_INTERVAL: f64 = 1.;
+
 fn init_logger(level: LevelFilter) {
     Builder::new()
         .filter(None, level)
@@ -48,8 +72,14 @@ fn init_logger(level: LevelFilter) {
 }
 
 fn build_system(config: &TestConfig) -> System<JsonMessage> {
+    build_system_with_clocks(config).0
+}
+
+// also returns the clock skews of nodes, which is needed to change them during the test
+fn build_system_with_clocks(config: &TestConfig) -> (System<JsonMessage>, HashMap<String, f64>) {
     let mut sys = System::with_seed(config.seed);
     sys.set_delays(0.01, 0.1);
+    let mut clock_skews = HashMap::new();
     for n in 0..config.node_count {
         let node_id = format!("{}", n);
         let node = config
@@ -58,8 +88,9 @@ fn build_system(config: &TestConfig) -> System<JsonMessage> {
         sys.add_node(rc!(refcell!(node)));
         let clock_skew = sys.gen_range(0.0..10.0);
         sys.set_clock_skew(&node_id, clock_skew);
+        clock_skews.insert(node_id, clock_skew);
     }
-    return sys;
+    return (sys, clock_skews);
 }
 
 fn recover_node(node_id: &str, sys: &mut System<JsonMessage>, config: &TestConfig) {
@@ -67,7 +98,60 @@ fn recover_node(node_id: &str, sys: &mut System<JsonMessage>, config: &TestConfi
     sys.add_node(rc!(refcell!(node)));
 }
 
-fn step_until_stabilized(sys: &mut System<JsonMessage>, group: HashSet<String>) -> TestResult {
+// waits for MEMBERS response skipping membership change notifications,
+// which are checked separately using the history of local events
+fn read_members(sys: &mut System<JsonMessage>, node: &str) -> Result<Vec<String>, String> {
+    loop {
+        let res = sys.step_until_local_message_with_timeout(node, 10.);
+        assume!(
+            res.is_ok(),
+            format!("Members list is not returned by {}", node)
+        )?;
+        let msgs = res.unwrap();
+        for msg in msgs.iter() {
+            if NOTIFICATION_TYPES.contains(&msg.tip.as_str()) {
+                continue;
+            }
+            assume!(msg.tip == "MEMBERS", "Wrong message type")?;
+            let data: MembersMessage = serde_json::from_str(&msg.data).unwrap();
+            return Ok(data.members);
+        }
+    }
+}
+
+fn get_members(sys: &mut System<JsonMessage>, node: &str) -> Result<Vec<String>, String> {
+    sys.send_local(
+        JsonMessage::from("GET_MEMBERS", &GetMembersMessage {}),
+        node,
+    );
+    read_members(sys, node)
+}
+
+// requests members lists from all nodes at once instead of waiting for each response in turn,
+// which makes polling of large groups much faster
+fn get_members_batch(
+    sys: &mut System<JsonMessage>,
+    nodes: &[String],
+) -> Result<HashMap<String, HashSet<String>>, String> {
+    for node in nodes {
+        sys.send_local(
+            JsonMessage::from("GET_MEMBERS", &GetMembersMessage {}),
+            node,
+        );
+    }
+    let mut memberlists = HashMap::new();
+    for node in nodes {
+        let members = read_members(sys, node)?;
+        memberlists.insert(node.clone(), members.into_iter().collect());
+    }
+    Ok(memberlists)
+}
+
+fn step_until_stabilized(
+    sys: &mut System<JsonMessage>,
+    group: HashSet<String>,
+    config: &TestConfig,
+) -> TestResult {
     let max_time = sys.time() + 300.; // timeout is 5 minutes
     let mut stabilized = HashSet::new();
     let mut memberlists = HashMap::new();
@@ -76,24 +160,15 @@ fn step_until_stabilized(sys: &mut System<JsonMessage>, group: HashSet<String>)
         sys.step_for_duration(5.);
         stabilized.clear();
         for node in group.iter() {
-            sys.send_local(
-                JsonMessage::from("GET_MEMBERS", &GetMembersMessage {}),
-                &node,
-            );
-            let res = sys.step_until_local_message_with_timeout(&node, 10.);
-            assume!(
-                res.is_ok(),
-                format!("Members list is not returned by {}", &node)
-            )?;
-            let msgs = res.unwrap();
-            let msg = msgs.first().unwrap();
-            assume!(msg.tip == "MEMBERS", "Wrong message type")?;
-            let data: MembersMessage = serde_json::from_str(&msg.data).unwrap();
-            let members: HashSet<String> = data.members.clone().into_iter().collect();
+            let members_list = get_members(sys, node)?;
+            let members: HashSet<String> = members_list.clone().into_iter().collect();
             if members.eq(&group) {
                 stabilized.insert(node.clone());
             }
-            memberlists.insert(node.clone(), data.members);
+            memberlists.insert(node.clone(), members_list);
+        }
+        if let Some(path) = &config.timeseries {
+            record_views(sys, &group, &memberlists, path);
         }
         // if !cont {
         //     break;
@@ -117,6 +192,436 @@ fn step_until_stabilized(sys: &mut System<JsonMessage>, group: HashSet<String>)
     Ok(true)
 }
 
+thread_local! {
+    // time series files which are already created in this run
+    static TIMESERIES_FILES: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+// appends the results of members lists poll to the time series file of the test (CSV),
+// one row per node: time, node, view size, missing/extra members, network load, view,
+// the file is created (or truncated) on the first poll in the test
+fn record_views(
+    sys: &System<JsonMessage>,
+    group: &HashSet<String>,
+    memberlists: &HashMap<String, Vec<String>>,
+    path: &str,
+) {
+    let created = TIMESERIES_FILES.with(|files| !files.borrow_mut().insert(path.to_string()));
+    let mut file = if created {
+        std::fs::OpenOptions::new().append(true).open(path).unwrap()
+    } else {
+        let mut file = std::fs::File::create(path).unwrap();
+        writeln!(
+            file,
+            "time,node,view_size,missing,extra,messages,traffic,view"
+        )
+        .unwrap();
+        file
+    };
+    let mut nodes = group.iter().collect::<Vec<_>>();
+    nodes.sort();
+    for node in nodes {
+        let mut members = memberlists[node].clone();
+        members.sort();
+        let missing = group.iter().filter(|n| !members.contains(n)).count();
+        let extra = members.iter().filter(|n| !group.contains(*n)).count();
+        writeln!(
+            file,
+            "{:.3},{},{},{},{},{},{},{}",
+            sys.time(),
+            node,
+            members.len(),
+            missing,
+            extra,
+            sys.get_network_message_count(),
+            sys.get_network_traffic(),
+            members.join(" ")
+        )
+        .unwrap();
+    }
+}
+
+// returns config with the path of the time series file for the given test
+fn with_timeseries<'a>(config: &TestConfig<'a>, test: &str) -> TestConfig<'a> {
+    let mut test_config = config.clone();
+    if let Some(dir) = &config.timeseries {
+        let name = test
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        test_config.timeseries = Some(format!("{}/{}.csv", dir, name));
+    }
+    test_config
+}
+
+fn add_test<'a>(
+    tests: &mut TestSuite<TestConfig<'a>>,
+    name: &str,
+    test: fn(&TestConfig<'a>) -> TestResult,
+    config: &TestConfig<'a>,
+) {
+    tests.add(name, test, with_timeseries(config, name));
+}
+
+// measures the time until the failed node is removed from the members list
+// by the first group member (detection) and by all group members (dissemination)
+fn measure_detection(
+    sys: &mut System<JsonMessage>,
+    group: &[String],
+    failed: &str,
+    config: &TestConfig,
+) -> TestResult {
+    let start_time = sys.time();
+    let max_time = start_time + 300.;
+    let mut detection_time = None;
+    let mut dissemination_time = None;
+    while dissemination_time.is_none() && sys.time() < max_time {
+        sys.step_for_duration(POLL_INTERVAL);
+        let mut detected = 0;
+        for node in group {
+            if !get_members(sys, node)?.iter().any(|n| n == failed) {
+                detected += 1;
+            }
+        }
+        let elapsed = sys.time() - start_time;
+        if detected > 0 && detection_time.is_none() {
+            detection_time = Some(elapsed);
+        }
+        if detected == group.len() {
+            dissemination_time = Some(elapsed);
+        }
+    }
+    let format_time = |t: Option<f64>| match t {
+        Some(t) => format!("{:.2}", t),
+        None => "-".to_string(),
+    };
+    println!(
+        "- failure of {}: detection time - {}, dissemination time - {}",
+        failed,
+        format_time(detection_time),
+        format_time(dissemination_time)
+    );
+    let detection_time = detection_time.ok_or(format!(
+        "Failure of {} is not detected by any member",
+        failed
+    ))?;
+    let dissemination_time = dissemination_time.ok_or(format!(
+        "Failure of {} is not detected by all members",
+        failed
+    ))?;
+    if let Some(max) = config.max_detection_time {
+        assume!(
+            detection_time <= max,
+            format!(
+                "Failure detection is too slow: {:.2} > {:.2}",
+                detection_time, max
+            )
+        )?;
+    }
+    if let Some(max) = config.max_dissemination_time {
+        assume!(
+            dissemination_time <= max,
+            format!(
+                "Failure dissemination is too slow: {:.2} > {:.2}",
+                dissemination_time, max
+            )
+        )?;
+    }
+    Ok(true)
+}
+
+// makes the given number of steps while polling members lists at a fixed rate
+// and counting how many times a live node was removed from some members list
+fn step_with_false_positives(
+    sys: &mut System<JsonMessage>,
+    group: &[String],
+    step_count: u32,
+    config: &TestConfig,
+) -> TestResult {
+    let mut memberlists: HashMap<String, HashSet<String>> = HashMap::new();
+    let mut false_positives = 0;
+    let mut next_poll = sys.time();
+    for _ in 0..step_count {
+        sys.step();
+        if sys.time() < next_poll {
+            continue;
+        }
+        for node in group {
+            let members = get_members(sys, node)?.into_iter().collect::<HashSet<_>>();
+            if let Some(prev_members) = memberlists.get(node) {
+                false_positives += prev_members
+                    .difference(&members)
+                    .filter(|n| group.contains(n))
+                    .count();
+            }
+            memberlists.insert(node.clone(), members);
+        }
+        next_poll = sys.time() + POLL_INTERVAL;
+    }
+    println!("- false positives: {}", false_positives);
+    if let Some(max) = config.max_false_positives {
+        assume!(
+            false_positives <= max as usize,
+            format!("Too many false positives: {} > {}", false_positives, max)
+        )?;
+    }
+    Ok(true)
+}
+
+// checks that membership change notifications emitted by group members are consistent
+// with their members lists, the nodes which left or crashed are reported with proper type
+// and the live nodes are not reported as removed too often
+fn check_notifications(
+    sys: &System<JsonMessage>,
+    group: &[String],
+    left: &[String],
+    crashed: &[String],
+    config: &TestConfig,
+) -> TestResult {
+    let expected: HashSet<String> = group.iter().cloned().collect();
+    let mut consistent = true;
+    let mut types_ok = true;
+    let mut flips_ok = true;
+    let mut final_ok = true;
+    for node in group {
+        let mut view = HashSet::new();
+        let mut flips = 0;
+        for e in sys.get_local_events(node) {
+            if !matches!(e.tip, LocalEventType::LocalMessageSend) {
+                continue;
+            }
+            let msg = e.msg.unwrap();
+            if msg.tip == "MEMBERS" {
+                let data: MembersMessage = serde_json::from_str(&msg.data).unwrap();
+                let members: HashSet<String> = data.members.into_iter().collect();
+                if members != view {
+                    println!(
+                        "- [{}] members list at time {:.2} does not match notifications",
+                        node, e.time
+                    );
+                    consistent = false;
+                }
+                continue;
+            }
+            if !NOTIFICATION_TYPES.contains(&msg.tip.as_str()) {
+                continue;
+            }
+            let data: MemberChangeMessage = serde_json::from_str(&msg.data).unwrap();
+            let member = data.member;
+            let changed = if msg.tip == "MEMBER_JOINED" {
+                view.insert(member.clone())
+            } else {
+                view.remove(&member)
+            };
+            if !changed {
+                println!(
+                    "- [{}] {} {} does not change members list",
+                    node, msg.tip, member
+                );
+                consistent = false;
+            }
+            if msg.tip == "MEMBER_JOINED" {
+                continue;
+            }
+            if expected.contains(&member) {
+                flips += 1;
+            } else if (left.contains(&member) && msg.tip != "MEMBER_LEFT")
+                || (crashed.contains(&member) && msg.tip != "MEMBER_FAILED")
+            {
+                println!("- [{}] {} {} has wrong type", node, msg.tip, member);
+                types_ok = false;
+            }
+        }
+        if flips > config.max_flips {
+            println!("- [{}] live nodes were removed {} times", node, flips);
+            flips_ok = false;
+        }
+        if view != expected {
+            let mut members = view.into_iter().collect::<Vec<_>>();
+            members.sort();
+            println!(
+                "- [{}] members list from notifications: {}",
+                node,
+                members.join(", ")
+            );
+            final_ok = false;
+        }
+    }
+    assume!(consistent, "Notifications do not match members lists")?;
+    assume!(types_ok, "Wrong type of notifications")?;
+    assume!(flips_ok, "Too many spurious notifications about live nodes")?;
+    assume!(final_ok, "Notifications do not match the final group")?;
+    Ok(true)
+}
+
+// emulates clocks running at different rates by periodically adjusting clock skews of nodes
+fn step_with_clock_drift(
+    sys: &mut System<JsonMessage>,
+    clock_skews: &mut HashMap<String, f64>,
+    drift_rates: &HashMap<String, f64>,
+    duration: f64,
+) {
+    let end_time = sys.time() + duration;
+    while sys.time() < end_time {
+        sys.step_for_duration(POLL_INTERVAL);
+        for (node, rate) in drift_rates {
+            let clock_skew = clock_skews.get_mut(node).unwrap();
+            *clock_skew += rate * POLL_INTERVAL;
+            sys.set_clock_skew(node, *clock_skew);
+        }
+    }
+}
+
+// returns nodes which are not reachable from the first node of the group in the graph,
+// where nodes are linked if one of them knows about the other
+fn disconnected_nodes(
+    group: &[String],
+    memberlists: &HashMap<String, HashSet<String>>,
+) -> Vec<String> {
+    let mut connected = HashSet::new();
+    let mut queue = vec![group[0].clone()];
+    while let Some(node) = queue.pop() {
+        if !connected.insert(node.clone()) {
+            continue;
+        }
+        for other in group {
+            if !connected.contains(other)
+                && (memberlists[&node].contains(other) || memberlists[other].contains(&node))
+            {
+                queue.push(other.clone());
+            }
+        }
+    }
+    let mut disconnected = group
+        .iter()
+        .filter(|node| !connected.contains(*node))
+        .cloned()
+        .collect::<Vec<_>>();
+    disconnected.sort();
+    disconnected
+}
+
+// checks that members lists do not split the group into disjoint parts
+fn check_views_connected(
+    group: &[String],
+    memberlists: &HashMap<String, HashSet<String>>,
+) -> TestResult {
+    let disconnected = disconnected_nodes(group, memberlists);
+    if !disconnected.is_empty() {
+        return Err(format!(
+            "Group is split: nodes {} are not connected to {}",
+            disconnected.join(", "),
+            group[0]
+        ));
+    }
+    Ok(true)
+}
+
+// checks partial views of group members (active views in HyParView terms):
+// each view is non-empty, bounded and contains only group members, the overlay is connected
+// and every node is known to at least one and at most 2 * view_size other nodes
+fn check_partial_views(
+    group: &[String],
+    memberlists: &HashMap<String, HashSet<String>>,
+    view_size: usize,
+) -> Result<(), String> {
+    let mut in_degrees: HashMap<&String, usize> = group.iter().map(|node| (node, 0)).collect();
+    for node in group {
+        let view = &memberlists[node];
+        if view.is_empty() || view.len() > view_size {
+            return Err(format!("View size of {} is {}", node, view.len()));
+        }
+        for member in view {
+            match in_degrees.get_mut(member) {
+                Some(in_degree) => *in_degree += 1,
+                None => return Err(format!("View of {} contains {}", node, member)),
+            }
+        }
+    }
+    let disconnected = disconnected_nodes(group, memberlists);
+    if !disconnected.is_empty() {
+        return Err(format!(
+            "Overlay is not connected: nodes {} are not reachable from {}",
+            disconnected.join(", "),
+            group[0]
+        ));
+    }
+    for (node, in_degree) in in_degrees {
+        if in_degree == 0 || in_degree > 2 * view_size {
+            return Err(format!("In-degree of {} is {}", node, in_degree));
+        }
+    }
+    Ok(())
+}
+
+fn step_until_overlay_stabilized(
+    sys: &mut System<JsonMessage>,
+    group: &[String],
+    config: &TestConfig,
+) -> TestResult {
+    let view_size = config.partial_view.unwrap() as usize;
+    let max_time = sys.time() + 300.; // timeout is 5 minutes
+    let mut memberlists;
+    let mut res;
+    loop {
+        sys.step_for_duration(5.);
+        memberlists = get_members_batch(sys, group)?;
+        if let Some(path) = &config.timeseries {
+            let views = memberlists
+                .iter()
+                .map(|(node, view)| (node.clone(), view.iter().cloned().collect()))
+                .collect();
+            record_views(sys, &group.iter().cloned().collect(), &views, path);
+        }
+        res = check_partial_views(group, &memberlists, view_size);
+        if res.is_ok() || sys.time() >= max_time {
+            break;
+        }
+    }
+    assume!(
+        res.is_ok(),
+        format!("Overlay is not stabilized: {}", res.unwrap_err())
+    )?;
+
+    let in_degrees = group
+        .iter()
+        .map(|node| {
+            memberlists
+                .values()
+                .filter(|view| view.contains(node))
+                .count()
+        })
+        .collect::<Vec<_>>();
+    println!(
+        "N = {}: time - {:.2}, in-degree min - {}, avg - {:.2}, max - {}",
+        group.len(),
+        sys.time(),
+        in_degrees.iter().min().unwrap(),
+        in_degrees.iter().sum::<usize>() as f64 / group.len() as f64,
+        in_degrees.iter().max().unwrap()
+    );
+    Ok(true)
+}
+
+// randomly splits the (shuffled) group into the given number of non-empty parts
+fn split_group(group: &[String], part_count: usize) -> Vec<Vec<String>> {
+    let mut parts = vec![Vec::new(); part_count];
+    for (i, node) in group.iter().enumerate() {
+        parts[i % part_count].push(node.clone());
+    }
+    parts
+}
+
+// disables message delivery from each of src nodes to each of dst nodes
+fn disable_links(sys: &mut System<JsonMessage>, src: &[String], dst: &[String]) {
+    for from in src {
+        for to in dst {
+            sys.disable_link(from, to);
+        }
+    }
+}
+
 // TESTS -------------------------------------------------------------------------------------------
 
 fn test_simple(config: &TestConfig) -> TestResult {
@@ -126,7 +631,7 @@ fn test_simple(config: &TestConfig) -> TestResult {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), &node);
     }
     let group = sys.get_node_ids().clone().into_iter().collect();
-    step_until_stabilized(&mut sys, group)
+    step_until_stabilized(&mut sys, group, config)
 }
 
 fn test_random_seed(config: &TestConfig) -> TestResult {
@@ -141,7 +646,7 @@ fn test_random_seed(config: &TestConfig) -> TestResult {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), &node);
         group.push(node);
     }
-    step_until_stabilized(&mut sys, group.into_iter().collect())
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
 }
 
 fn test_node_join(config: &TestConfig) -> TestResult {
@@ -157,12 +662,12 @@ fn test_node_join(config: &TestConfig) -> TestResult {
             sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
         }
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // node joins the system
     sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), &new_node);
     group.push(new_node);
-    step_until_stabilized(&mut sys, group.into_iter().collect())
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
 }
 
 fn test_node_leave(config: &TestConfig) -> TestResult {
@@ -175,12 +680,12 @@ fn test_node_leave(config: &TestConfig) -> TestResult {
     for node in &group {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // node leaves the system
     let left_node = group.remove(rand.gen_range(0..group.len()));
     sys.send_local(JsonMessage::from("LEAVE", &LeaveMessage {}), &left_node);
-    step_until_stabilized(&mut sys, group.into_iter().collect())
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
 }
 
 fn test_node_crash(config: &TestConfig) -> TestResult {
@@ -193,12 +698,12 @@ fn test_node_crash(config: &TestConfig) -> TestResult {
     for node in &group {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // node crashes
     let crashed_node = group.remove(rand.gen_range(0..group.len()));
     sys.crash_node(&crashed_node);
-    step_until_stabilized(&mut sys, group.into_iter().collect())
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
 }
 
 fn test_seed_node_crash(config: &TestConfig) -> TestResult {
@@ -211,12 +716,12 @@ fn test_seed_node_crash(config: &TestConfig) -> TestResult {
     for node in &group {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // seed node crashes
     group.remove(0);
     sys.crash_node(&seed);
-    step_until_stabilized(&mut sys, group.into_iter().collect())
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
 }
 
 fn test_node_crash_recover(config: &TestConfig) -> TestResult {
@@ -229,12 +734,12 @@ fn test_node_crash_recover(config: &TestConfig) -> TestResult {
     for node in &group {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // node crashes
     let crashed_node = group.remove(rand.gen_range(0..group.len()));
     sys.crash_node(&crashed_node);
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // node recovers
     recover_node(&crashed_node, &mut sys, &config);
@@ -243,7 +748,139 @@ fn test_node_crash_recover(config: &TestConfig) -> TestResult {
         &crashed_node,
     );
     group.push(crashed_node);
-    step_until_stabilized(&mut sys, group.into_iter().collect())
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
+}
+
+fn test_node_rapid_crash_recover(config: &TestConfig) -> TestResult {
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut sys = build_system(config);
+    let mut group = sys.get_node_ids();
+    group.shuffle(&mut rand);
+    let seed = &group.get(0).cloned().unwrap();
+
+    for node in &group {
+        sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
+    }
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // node crashes and recovers with the same id before its failure is detected
+    let crashed_node = group.get(rand.gen_range(1..group.len())).unwrap().clone();
+    sys.crash_node(&crashed_node);
+    sys.step_for_duration(0.5);
+    recover_node(&crashed_node, &mut sys, config);
+    sys.send_local(
+        JsonMessage::from("JOIN", &JoinMessage { seed }),
+        &crashed_node,
+    );
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
+}
+
+fn test_node_flapping(config: &TestConfig) -> TestResult {
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut sys = build_system(config);
+    let mut group = sys.get_node_ids();
+    group.shuffle(&mut rand);
+    let seed = &group.get(0).cloned().unwrap();
+
+    for node in &group {
+        sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
+    }
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // node repeatedly crashes and recovers with the same id
+    let flapping_node = group.get(rand.gen_range(1..group.len())).unwrap().clone();
+    for _ in 0..5 {
+        sys.crash_node(&flapping_node);
+        sys.step_for_duration(rand.gen_range(0.5..5.0));
+        recover_node(&flapping_node, &mut sys, config);
+        sys.send_local(
+            JsonMessage::from("JOIN", &JoinMessage { seed }),
+            &flapping_node,
+        );
+        sys.step_for_duration(rand.gen_range(0.5..5.0));
+    }
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
+}
+
+fn test_node_recover_stale_rumor(config: &TestConfig) -> TestResult {
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut sys = build_system(config);
+    let mut group = sys.get_node_ids();
+    group.shuffle(&mut rand);
+    let seed = &group.get(0).cloned().unwrap();
+
+    for node in &group {
+        sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
+    }
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // node crashes
+    let crashed_node = group.remove(rand.gen_range(1..group.len()));
+    sys.crash_node(&crashed_node);
+
+    // wait until some member detects the failure, so the rumor about it starts spreading
+    let max_time = sys.time() + 300.;
+    let mut detected = false;
+    while !detected && sys.time() < max_time {
+        sys.step_for_duration(POLL_INTERVAL);
+        for node in group.iter() {
+            if !get_members(&mut sys, node)?.contains(&crashed_node) {
+                detected = true;
+                break;
+            }
+        }
+    }
+    assume!(
+        detected,
+        format!("Failure of {} is not detected", crashed_node)
+    )?;
+
+    // node recovers while the rumor about its failure is still circulating
+    recover_node(&crashed_node, &mut sys, config);
+    sys.send_local(
+        JsonMessage::from("JOIN", &JoinMessage { seed }),
+        &crashed_node,
+    );
+    group.push(crashed_node);
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
+}
+
+fn test_rolling_restart(config: &TestConfig) -> TestResult {
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut sys = build_system(config);
+    let mut group = sys.get_node_ids();
+    group.shuffle(&mut rand);
+    let seed = &group.get(0).cloned().unwrap();
+
+    for node in &group {
+        sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
+    }
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // restarted node joins via some other live node, so a single node is not restarted
+    if group.len() < 2 {
+        return Ok(true);
+    }
+
+    // every node including the original seed is restarted in turn,
+    // while the rest of the group keeps running
+    let mut restart_order = group.clone();
+    restart_order.shuffle(&mut rand);
+    for node in restart_order.iter() {
+        sys.crash_node(node);
+        sys.step_for_duration(rand.gen_range(1.0..5.0));
+        recover_node(node, &mut sys, config);
+        let others = group.iter().filter(|n| *n != node).collect::<Vec<_>>();
+        let live_seed = *others.choose(&mut rand).unwrap();
+        sys.send_local(
+            JsonMessage::from("JOIN", &JoinMessage { seed: live_seed }),
+            node,
+        );
+        sys.step_for_duration(rand.gen_range(5.0..20.0));
+        let memberlists = get_members_batch(&mut sys, &group)?;
+        check_views_connected(&group, &memberlists)?;
+    }
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
 }
 
 fn test_node_offline(config: &TestConfig) -> TestResult {
@@ -256,12 +893,12 @@ fn test_node_offline(config: &TestConfig) -> TestResult {
     for node in &group {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // node goes offline
     let offline_node = group.remove(rand.gen_range(0..group.len()));
     sys.disconnect_node(&offline_node);
-    step_until_stabilized(&mut sys, group.into_iter().collect())
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
 }
 
 fn test_seed_node_offline(config: &TestConfig) -> TestResult {
@@ -274,12 +911,12 @@ fn test_seed_node_offline(config: &TestConfig) -> TestResult {
     for node in &group {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // seed node goes offline
     group.remove(0);
     sys.disconnect_node(&seed);
-    step_until_stabilized(&mut sys, group.into_iter().collect())
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
 }
 
 fn test_node_offline_recover(config: &TestConfig) -> TestResult {
@@ -292,17 +929,17 @@ fn test_node_offline_recover(config: &TestConfig) -> TestResult {
     for node in &group {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // node goes offline
     let offline_node = group.remove(rand.gen_range(0..group.len()));
     sys.disconnect_node(&offline_node);
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // node goes back online
     sys.connect_node(&offline_node);
     group.push(offline_node);
-    step_until_stabilized(&mut sys, group.into_iter().collect())
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
 }
 
 fn test_network_partition(config: &TestConfig) -> TestResult {
@@ -315,7 +952,7 @@ fn test_network_partition(config: &TestConfig) -> TestResult {
     for node in &group {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // network is partitioned
     let (group1, group2): (Vec<_>, Vec<_>) = group
@@ -323,8 +960,16 @@ fn test_network_partition(config: &TestConfig) -> TestResult {
         .map(|s| &**s)
         .partition(|_| rand.gen_range(0.0..1.0) > 0.6);
     sys.make_partition(&group1, &group2);
-    step_until_stabilized(&mut sys, group1.into_iter().map(String::from).collect())?;
-    step_until_stabilized(&mut sys, group2.into_iter().map(String::from).collect())
+    step_until_stabilized(
+        &mut sys,
+        group1.into_iter().map(String::from).collect(),
+        config,
+    )?;
+    step_until_stabilized(
+        &mut sys,
+        group2.into_iter().map(String::from).collect(),
+        config,
+    )
 }
 
 fn test_network_partition_recover(config: &TestConfig) -> TestResult {
@@ -337,7 +982,7 @@ fn test_network_partition_recover(config: &TestConfig) -> TestResult {
     for node in &group {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // network is partitioned
     let (group1, group2): (Vec<_>, Vec<_>) = group
@@ -345,12 +990,24 @@ fn test_network_partition_recover(config: &TestConfig) -> TestResult {
         .map(|s| &**s)
         .partition(|_| rand.gen_range(0.0..1.0) > 0.6);
     sys.make_partition(&group1, &group2);
-    step_until_stabilized(&mut sys, group1.into_iter().map(String::from).collect())?;
-    step_until_stabilized(&mut sys, group2.into_iter().map(String::from).collect())?;
+    step_until_stabilized(
+        &mut sys,
+        group1.into_iter().map(String::from).collect(),
+        config,
+    )?;
+    step_until_stabilized(
+        &mut sys,
+        group2.into_iter().map(String::from).collect(),
+        config,
+    )?;
 
     // network is recovered
     sys.reset_network();
-    step_until_stabilized(&mut sys, group.into_iter().map(String::from).collect())
+    step_until_stabilized(
+        &mut sys,
+        group.into_iter().map(String::from).collect(),
+        config,
+    )
 }
 
 fn test_node_cannot_receive(config: &TestConfig) -> TestResult {
@@ -363,12 +1020,12 @@ fn test_node_cannot_receive(config: &TestConfig) -> TestResult {
     for node in &group {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // node goes partially offline (cannot receive incoming messages)
     let blocked_node = group.remove(rand.gen_range(0..group.len()));
     sys.drop_incoming(&blocked_node);
-    step_until_stabilized(&mut sys, group.into_iter().collect())
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
 }
 
 fn test_node_cannot_send(config: &TestConfig) -> TestResult {
@@ -381,12 +1038,98 @@ fn test_node_cannot_send(config: &TestConfig) -> TestResult {
     for node in &group {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // node goes partially offline (cannot send outgoing messages)
     let blocked_node = group.remove(rand.gen_range(0..group.len()));
     sys.drop_outgoing(&blocked_node);
-    step_until_stabilized(&mut sys, group.into_iter().collect())
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
+}
+
+fn test_one_way_partition(config: &TestConfig) -> TestResult {
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut sys = build_system(config);
+    let mut group = sys.get_node_ids();
+    group.shuffle(&mut rand);
+    let seed = &group.get(0).unwrap();
+
+    for node in &group {
+        sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
+    }
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // messages from group1 to group2 are lost, but not in the opposite direction,
+    // so the nodes from different groups cannot communicate in both directions
+    let parts = split_group(&group, 2);
+    disable_links(&mut sys, &parts[0], &parts[1]);
+    for part in parts.iter() {
+        step_until_stabilized(&mut sys, part.clone().into_iter().collect(), config)?;
+    }
+
+    // network is recovered
+    sys.reset_network();
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
+}
+
+fn test_three_way_partition(config: &TestConfig) -> TestResult {
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut sys = build_system(config);
+    let mut group = sys.get_node_ids();
+    group.shuffle(&mut rand);
+    let seed = &group.get(0).unwrap();
+
+    for node in &group {
+        sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
+    }
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // network is partitioned into three isolated groups
+    let parts = split_group(&group, 3);
+    for part1 in parts.iter() {
+        for part2 in parts.iter() {
+            if part1 != part2 {
+                disable_links(&mut sys, part1, part2);
+            }
+        }
+    }
+    for part in parts.iter() {
+        step_until_stabilized(&mut sys, part.clone().into_iter().collect(), config)?;
+    }
+
+    // network is recovered
+    sys.reset_network();
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
+}
+
+fn test_bridge_node(config: &TestConfig) -> TestResult {
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut sys = build_system(config);
+    let mut group = sys.get_node_ids();
+    group.shuffle(&mut rand);
+    let seed = &group.get(0).unwrap();
+
+    for node in &group {
+        sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
+    }
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // two groups cannot communicate with each other, but both can communicate with the bridge node,
+    // so all nodes are still able to communicate with some group member and should stay in the group
+    let mut others = group.clone();
+    let bridge = others.remove(rand.gen_range(0..others.len()));
+    let parts = split_group(&others, 2);
+    disable_links(&mut sys, &parts[0], &parts[1]);
+    disable_links(&mut sys, &parts[1], &parts[0]);
+    // run for a while
+    sys.steps(1000);
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // bridge node crashes and the groups become isolated
+    sys.crash_node(&bridge);
+    for part in parts.iter() {
+        step_until_stabilized(&mut sys, part.clone().into_iter().collect(), config)?;
+    }
+    Ok(true)
 }
 
 fn test_two_nodes_cannot_communicate(config: &TestConfig) -> TestResult {
@@ -399,7 +1142,7 @@ fn test_two_nodes_cannot_communicate(config: &TestConfig) -> TestResult {
     for node in &group {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // two nodes cannot communicate with each other
     let node1 = *seed;
@@ -411,7 +1154,7 @@ fn test_two_nodes_cannot_communicate(config: &TestConfig) -> TestResult {
     sys.disable_link(&node2, &node1);
     // run for a while
     sys.steps(1000);
-    step_until_stabilized(&mut sys, group.into_iter().collect())
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
 }
 
 fn test_slow_network(config: &TestConfig) -> TestResult {
@@ -424,13 +1167,13 @@ fn test_slow_network(config: &TestConfig) -> TestResult {
     for node in &group {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // slow down network for a while
     sys.set_delays(0.1, 1.0);
     sys.steps(200);
     sys.set_delays(0.01, 0.1);
-    step_until_stabilized(&mut sys, group.into_iter().collect())
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
 }
 
 fn test_flaky_network(config: &TestConfig) -> TestResult {
@@ -443,13 +1186,13 @@ fn test_flaky_network(config: &TestConfig) -> TestResult {
     for node in &group {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // make network unreliable for a while
     sys.set_drop_rate(0.5);
     sys.steps(1000);
     sys.set_drop_rate(0.0);
-    step_until_stabilized(&mut sys, group.into_iter().collect())
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
 }
 
 fn test_flaky_network_on_start(config: &TestConfig) -> TestResult {
@@ -466,7 +1209,7 @@ fn test_flaky_network_on_start(config: &TestConfig) -> TestResult {
     }
     sys.steps(1000);
     sys.set_drop_rate(0.0);
-    step_until_stabilized(&mut sys, group.into_iter().collect())
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
 }
 
 fn test_flaky_network_and_crash(config: &TestConfig) -> TestResult {
@@ -479,7 +1222,7 @@ fn test_flaky_network_and_crash(config: &TestConfig) -> TestResult {
     for node in &group {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     // make network unreliable for a while + crash node
     sys.set_drop_rate(0.5);
@@ -487,7 +1230,190 @@ fn test_flaky_network_and_crash(config: &TestConfig) -> TestResult {
     sys.crash_node(&crashed_node);
     sys.steps(1000);
     sys.set_drop_rate(0.0);
-    step_until_stabilized(&mut sys, group.into_iter().collect())
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
+}
+
+fn test_detection_time(config: &TestConfig) -> TestResult {
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut sys = build_system(config);
+    let mut group = sys.get_node_ids();
+    group.shuffle(&mut rand);
+    let seed = &group.get(0).cloned().unwrap();
+
+    for node in &group {
+        sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
+    }
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // random node crashes
+    let crashed_node = group.remove(rand.gen_range(0..group.len()));
+    sys.crash_node(&crashed_node);
+    measure_detection(&mut sys, &group, &crashed_node, config)?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // seed node crashes
+    if seed != &crashed_node {
+        group.retain(|node| node != seed);
+        sys.crash_node(seed);
+        measure_detection(&mut sys, &group, seed, config)?;
+    }
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
+}
+
+fn test_false_positives(config: &TestConfig) -> TestResult {
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut sys = build_system(config);
+    let mut group = sys.get_node_ids();
+    group.shuffle(&mut rand);
+    let seed = &group.get(0).unwrap();
+
+    for node in &group {
+        sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
+    }
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // slow down network for a while
+    sys.set_delays(0.1, 1.0);
+    step_with_false_positives(&mut sys, &group, 200, config)?;
+    sys.set_delays(0.01, 0.1);
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // make network unreliable for a while
+    sys.set_drop_rate(0.5);
+    step_with_false_positives(&mut sys, &group, 1000, config)?;
+    sys.set_drop_rate(0.0);
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
+}
+
+fn test_notifications(config: &TestConfig) -> TestResult {
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut sys = build_system(config);
+    let mut group = sys.get_node_ids();
+    group.shuffle(&mut rand);
+    let seed = &group.get(0).cloned().unwrap();
+
+    for node in &group {
+        sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
+    }
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // make network unreliable for a while
+    sys.set_drop_rate(0.3);
+    sys.steps(1000);
+    sys.set_drop_rate(0.0);
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // node leaves the system
+    let left_node = group.remove(rand.gen_range(1..group.len()));
+    sys.send_local(JsonMessage::from("LEAVE", &LeaveMessage {}), &left_node);
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // node crashes
+    let crashed_node = group.remove(rand.gen_range(1..group.len()));
+    sys.crash_node(&crashed_node);
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    check_notifications(&sys, &group, &[left_node], &[crashed_node], config)
+}
+
+fn test_clock_drift(config: &TestConfig) -> TestResult {
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let (mut sys, mut clock_skews) = build_system_with_clocks(config);
+    let mut group = sys.get_node_ids();
+    group.shuffle(&mut rand);
+    let seed = &group.get(0).unwrap();
+
+    for node in &group {
+        sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
+    }
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // node clocks run up to 10% slower or faster than real time
+    let mut drift_rates = HashMap::new();
+    for node in group.iter() {
+        drift_rates.insert(node.clone(), rand.gen_range(-0.1..0.1));
+    }
+    step_with_clock_drift(&mut sys, &mut clock_skews, &drift_rates, 60.);
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // node crashes while clocks continue to drift
+    let crashed_node = group.remove(rand.gen_range(0..group.len()));
+    sys.crash_node(&crashed_node);
+    drift_rates.remove(&crashed_node);
+    step_with_clock_drift(&mut sys, &mut clock_skews, &drift_rates, 60.);
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
+}
+
+fn test_clock_jump(config: &TestConfig) -> TestResult {
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let (mut sys, mut clock_skews) = build_system_with_clocks(config);
+    let mut group = sys.get_node_ids();
+    group.shuffle(&mut rand);
+    let seed = &group.get(0).unwrap();
+
+    for node in &group {
+        sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
+    }
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // clocks of some nodes jump forward (e.g. after NTP synchronization)
+    let jumped_nodes = group
+        .choose_multiple(&mut rand, group.len() / 2)
+        .cloned()
+        .collect::<Vec<_>>();
+    for node in jumped_nodes.iter() {
+        let clock_skew = clock_skews.get_mut(node).unwrap();
+        *clock_skew += 60.;
+        sys.set_clock_skew(node, *clock_skew);
+    }
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // clocks of the same nodes jump back to their previous values
+    for node in jumped_nodes.iter() {
+        let clock_skew = clock_skews.get_mut(node).unwrap();
+        *clock_skew -= 60.;
+        sys.set_clock_skew(node, *clock_skew);
+    }
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // node crashes after the clock jumps
+    let crashed_node = group.remove(rand.gen_range(0..group.len()));
+    sys.crash_node(&crashed_node);
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
+}
+
+fn test_partial_view(config: &TestConfig) -> TestResult {
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut sys = build_system(config);
+    let mut group = sys.get_node_ids();
+    group.shuffle(&mut rand);
+    let seed = &group.get(0).cloned().unwrap();
+
+    for node in &group {
+        sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
+    }
+    step_until_overlay_stabilized(&mut sys, &group, config)
+}
+
+fn test_partial_view_crash(config: &TestConfig) -> TestResult {
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut sys = build_system(config);
+    let mut group = sys.get_node_ids();
+    group.shuffle(&mut rand);
+    let seed = &group.get(0).cloned().unwrap();
+
+    for node in &group {
+        sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
+    }
+    step_until_overlay_stabilized(&mut sys, &group, config)?;
+
+    // 30% of nodes (possibly including the seed) crash at once
+    group.shuffle(&mut rand);
+    let crash_count = group.len() * 3 / 10;
+    for node in group.drain(..crash_count) {
+        sys.crash_node(&node);
+    }
+    step_until_overlay_stabilized(&mut sys, &group, config)
 }
 
 fn test_chaos_monkey(config: &TestConfig) -> TestResult {
@@ -500,7 +1426,7 @@ fn test_chaos_monkey(config: &TestConfig) -> TestResult {
     for node in &group {
         sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
     }
-    step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
     for _ in 0..5 {
         let p = rand.gen_range(0.0..1.0);
@@ -532,11 +1458,115 @@ fn test_chaos_monkey(config: &TestConfig) -> TestResult {
                 sys.disable_link(&node2, &node1);
             }
         }
-        step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+        step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
     }
     Ok(true)
 }
 
+fn test_churn(config: &TestConfig) -> TestResult {
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut sys = build_system(config);
+    let mut group = sys.get_node_ids();
+    group.shuffle(&mut rand);
+    let seed = &group.get(0).unwrap();
+
+    for node in &group {
+        sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
+    }
+    step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
+
+    // nodes join, leave, crash and recover as a Poisson process
+    let mut left = Vec::new();
+    let mut crashed = Vec::new();
+    let mut event_count = 0;
+    let mut accuracy_sum = 0.;
+    let mut sample_count = 0;
+    let next_event_delay =
+        |rand: &mut Pcg64| -(1. - rand.gen_range(0.0..1.0_f64)).ln() / config.churn_rate;
+    let end_time = sys.time() + config.churn_duration;
+    let mut next_event_time = sys.time() + next_event_delay(&mut rand);
+    while sys.time() < end_time {
+        sys.step_for_duration(POLL_INTERVAL);
+
+        // measure the fraction of live group members with accurate members list
+        let expected: HashSet<String> = group.clone().into_iter().collect();
+        let mut accurate = 0;
+        for node in group.iter() {
+            let members: HashSet<String> = get_members(&mut sys, node)?.into_iter().collect();
+            if members == expected {
+                accurate += 1;
+            }
+        }
+        accuracy_sum += accurate as f64 / group.len() as f64;
+        sample_count += 1;
+
+        if sys.time() < next_event_time {
+            continue;
+        }
+        next_event_time = sys.time() + next_event_delay(&mut rand);
+        let mut events = Vec::new();
+        if group.len() > 2 {
+            events.push("LEAVE");
+            events.push("CRASH");
+        }
+        if !left.is_empty() {
+            events.push("JOIN");
+        }
+        if !crashed.is_empty() {
+            events.push("RECOVER");
+        }
+        // too small group without left or crashed nodes can not change
+        let event = match events.choose(&mut rand) {
+            Some(event) => *event,
+            None => continue,
+        };
+        event_count += 1;
+        match event {
+            "LEAVE" => {
+                let left_node = group.remove(rand.gen_range(0..group.len()));
+                sys.send_local(JsonMessage::from("LEAVE", &LeaveMessage {}), &left_node);
+                left.push(left_node);
+            }
+            "CRASH" => {
+                let crashed_node = group.remove(rand.gen_range(0..group.len()));
+                sys.crash_node(&crashed_node);
+                crashed.push(crashed_node);
+            }
+            "JOIN" => {
+                let new_node = left.remove(rand.gen_range(0..left.len()));
+                let seed = group.choose(&mut rand).unwrap();
+                sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), &new_node);
+                group.push(new_node);
+            }
+            _ => {
+                let recovered_node = crashed.remove(rand.gen_range(0..crashed.len()));
+                recover_node(&recovered_node, &mut sys, config);
+                let seed = group.choose(&mut rand).unwrap();
+                sys.send_local(
+                    JsonMessage::from("JOIN", &JoinMessage { seed }),
+                    &recovered_node,
+                );
+                group.push(recovered_node);
+            }
+        }
+    }
+    let accuracy = accuracy_sum / sample_count as f64;
+    println!(
+        "- churn events: {}, average accuracy: {:.3}",
+        event_count, accuracy
+    );
+    assume!(
+        accuracy >= config.min_accuracy,
+        format!(
+            "Members lists are not accurate enough: {:.3} < {:.3}",
+            accuracy, config.min_accuracy
+        )
+    )?;
+
+    // churn stops
+    step_until_stabilized(&mut sys, group.into_iter().collect(), config)
+}
+
 fn test_scalability_normal(config: &TestConfig) -> TestResult {
     let sys_sizes = [
         config.node_count,
@@ -557,7 +1587,7 @@ fn test_scalability_normal(config: &TestConfig) -> TestResult {
             sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
         }
 
-        step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+        step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
         let init_time = sys.time();
         let init_net_traffic = sys.get_network_traffic();
         let init_msg_count = sys.get_network_message_count();
@@ -607,6 +1637,10 @@ fn test_scalability_normal(config: &TestConfig) -> TestResult {
     }
     assume!(scaling_ok, "Bad network load scaling")?;
     assume!(load_ratio_ok, "Bad max/min node load")?;
+    if let Some(max_rate) = config.max_message_rate {
+        let rate_ok = measurements.iter().all(|m| m.3 <= max_rate);
+        assume!(rate_ok, "Max node messages/s exceeds the limit")?;
+    }
     Ok(true)
 }
 
@@ -630,7 +1664,7 @@ fn test_scalability_crash(config: &TestConfig) -> TestResult {
             sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
         }
 
-        step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+        step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
         let init_time = sys.time();
         let init_net_traffic = sys.get_network_traffic();
         let init_msg_count = sys.get_network_message_count();
@@ -644,7 +1678,7 @@ fn test_scalability_crash(config: &TestConfig) -> TestResult {
 
         let crashed_node = group.remove(rand.gen_range(0..group.len()));
         sys.crash_node(&crashed_node);
-        step_until_stabilized(&mut sys, group.clone().into_iter().collect())?;
+        step_until_stabilized(&mut sys, group.clone().into_iter().collect(), config)?;
 
         let mut loads = Vec::new();
         for node in sys.get_node_ids() {
@@ -688,6 +1722,94 @@ fn test_scalability_crash(config: &TestConfig) -> TestResult {
     Ok(true)
 }
 
+fn test_scalability_large(config: &TestConfig) -> TestResult {
+    // sizes start from at least two nodes, so that the size grows and log(N) is positive
+    let mut node_count = config.node_count.max(2);
+    let max_node_count = config.large_scale.unwrap().max(node_count);
+    let mut sys_sizes = Vec::new();
+    while node_count < max_node_count {
+        sys_sizes.push(node_count);
+        node_count *= 4;
+    }
+    sys_sizes.push(max_node_count);
+
+    let mut measurements = Vec::new();
+    for node_count in sys_sizes.iter() {
+        let mut run_config = config.clone();
+        run_config.node_count = *node_count;
+        let mut rand = Pcg64::seed_from_u64(config.seed);
+        let mut sys = build_system(&run_config);
+        let mut group = sys.get_node_ids();
+        group.shuffle(&mut rand);
+        let seed = &group.get(0).unwrap();
+        for node in &group {
+            sys.send_local(JsonMessage::from("JOIN", &JoinMessage { seed }), node);
+        }
+
+        // measure time until all members lists are equal to the group
+        let init_time = sys.time();
+        let expected: HashSet<String> = group.clone().into_iter().collect();
+        let mut stabilized = false;
+        while !stabilized && sys.time() < init_time + 300. {
+            sys.step_for_duration(POLL_INTERVAL);
+            stabilized = get_members_batch(&mut sys, &group)?
+                .values()
+                .all(|members| *members == expected);
+        }
+        assume!(
+            stabilized,
+            format!(
+                "Group members lists are not stabilized for N = {}",
+                node_count
+            )
+        )?;
+        let stabilization_time = sys.time() - init_time;
+
+        // measure max node load in the stable group
+        let init_time = sys.time();
+        let mut init_loads = HashMap::new();
+        for node in sys.get_node_ids() {
+            init_loads.insert(
+                node.clone(),
+                sys.get_sent_message_count(&node) + sys.get_received_message_count(&node),
+            );
+        }
+        sys.step_for_duration(10.0);
+        let mut max_load = 0;
+        for node in sys.get_node_ids() {
+            let load = sys.get_sent_message_count(&node) + sys.get_received_message_count(&node);
+            max_load = max_load.max(load - init_loads.get(&node).unwrap());
+        }
+        let duration = sys.time() - init_time;
+        measurements.push((stabilization_time, max_load as f64 / duration));
+    }
+
+    let mut scaling_ok = true;
+    let mut rate_ok = true;
+    for i in 0..sys_sizes.len() {
+        let (time, max_rate) = measurements[i];
+        let log_size = (sys_sizes[i] as f64).log2();
+        println!(
+            "- N = {}: stabilization time - {:.2}, time/log(N) - {:.2}, max node messages/s - {:.2}",
+            sys_sizes[i], time, time / log_size, max_rate
+        );
+        if i > 0 {
+            let log_ratio = log_size / (sys_sizes[i - 1] as f64).log2();
+            if time / measurements[i - 1].0 > 2.0 * log_ratio {
+                scaling_ok = false;
+            }
+        }
+        if let Some(max) = config.max_message_rate {
+            if max_rate > max {
+                rate_ok = false;
+            }
+        }
+    }
+    assume!(scaling_ok, "Bad stabilization time scaling")?;
+    assume!(rate_ok, "Max node messages/s exceeds the limit")?;
+    Ok(true)
+}
+
 // CLI -----------------------------------------------------------------------------------------------------------------
 
 /// Membership Homework Tests
@@ -718,6 +1840,78 @@ struct Args {
     #[clap(long, short, default_value = "100")]
     monkeys: u32,
 
+    /// Enable tests of node restarts which require incarnation numbers
+    #[clap(long)]
+    incarnations: bool,
+
+    /// Enable test with all nodes restarted in turn (ROLLING RESTART)
+    #[clap(long)]
+    rolling_restart: bool,
+
+    /// Enable tests with drifting and jumping clocks of nodes
+    #[clap(long)]
+    clocks: bool,
+
+    /// Enable tests with one-way, three-way partitions and bridge node
+    #[clap(long)]
+    partitions: bool,
+
+    /// Enable failure detector measurements (DETECTION TIME and FALSE POSITIVES tests)
+    #[clap(long)]
+    detector: bool,
+
+    /// Max time for detecting a node crash by the first member (optional)
+    #[clap(long)]
+    max_detection_time: Option<f64>,
+
+    /// Max time for detecting a node crash by all members (optional)
+    #[clap(long)]
+    max_dissemination_time: Option<f64>,
+
+    /// Max number of removals of live nodes from members lists on slow/flaky network (optional)
+    #[clap(long)]
+    max_false_positives: Option<u32>,
+
+    /// Enable test with continuous churn (CHURN)
+    #[clap(long)]
+    churn: bool,
+
+    /// Rate of membership events (per second) in churn test
+    #[clap(long, default_value = "0.05")]
+    churn_rate: f64,
+
+    /// Duration of churn in churn test (in seconds)
+    #[clap(long, default_value = "600")]
+    churn_duration: f64,
+
+    /// Min average fraction of live nodes with accurate members list in churn test
+    #[clap(long, default_value = "0.5")]
+    min_accuracy: f64,
+
+    /// Max number of nodes in large-scale scalability test (enables SCALABILITY LARGE)
+    #[clap(long)]
+    large_scale: Option<u32>,
+
+    /// Max number of messages per second sent and received by a node in stable group (optional)
+    #[clap(long)]
+    max_message_rate: Option<f64>,
+
+    /// Enable test of membership change notifications (MEMBER_JOINED/MEMBER_LEFT/MEMBER_FAILED)
+    #[clap(long)]
+    notifications: bool,
+
+    /// Max number of notifications about removal of live nodes per node
+    #[clap(long, default_value = "2")]
+    max_flips: u32,
+
+    /// Max size of partial view returned by GET_MEMBERS (enables PARTIAL VIEW tests)
+    #[clap(long)]
+    partial_view: Option<u32>,
+
+    /// Directory for writing members lists time series of each test (CSV, one file per test)
+    #[clap(long)]
+    timeseries: Option<String>,
+
     /// Path to dslib directory
     #[clap(long = "lib", short = 'l', default_value = "../../dslib")]
     dslib_path: String,
@@ -737,72 +1931,183 @@ fn main() {
         node_factory: &node_factory,
         node_count: args.node_count,
         seed: args.seed,
+        max_detection_time: args.max_detection_time,
+        max_dissemination_time: args.max_dissemination_time,
+        max_false_positives: args.max_false_positives,
+        churn_rate: args.churn_rate,
+        churn_duration: args.churn_duration,
+        min_accuracy: args.min_accuracy,
+        large_scale: args.large_scale,
+        max_message_rate: args.max_message_rate,
+        max_flips: args.max_flips,
+        timeseries: args.timeseries,
+        partial_view: args.partial_view,
     };
+    if let Some(dir) = &config.timeseries {
+        std::fs::create_dir_all(dir).unwrap();
+    }
     let mut tests = TestSuite::new();
 
-    tests.add("SIMPLE", test_simple, config.clone());
-    tests.add("RANDOM SEED", test_random_seed, config.clone());
-    tests.add("NODE JOIN", test_node_join, config.clone());
-    tests.add("NODE LEAVE", test_node_leave, config.clone());
-    tests.add("NODE CRASH", test_node_crash, config.clone());
-    tests.add("SEED NODE CRASH", test_seed_node_crash, config.clone());
-    tests.add(
-        "NODE CRASH RECOVER",
-        test_node_crash_recover,
-        config.clone(),
-    );
-    tests.add("NODE OFFLINE", test_node_offline, config.clone());
-    tests.add("SEED NODE OFFLINE", test_seed_node_offline, config.clone());
-    tests.add(
-        "NODE OFFLINE RECOVER",
-        test_node_offline_recover,
-        config.clone(),
-    );
-    tests.add(
-        "NODE CANNOT RECEIVE",
-        test_node_cannot_receive,
-        config.clone(),
-    );
-    tests.add("NODE CANNOT SEND", test_node_cannot_send, config.clone());
-    tests.add("NETWORK PARTITION", test_network_partition, config.clone());
-    tests.add(
-        "NETWORK PARTITION RECOVER",
-        test_network_partition_recover,
-        config.clone(),
-    );
-    tests.add(
-        "TWO NODES CANNOT COMMUNICATE",
-        test_two_nodes_cannot_communicate,
-        config.clone(),
-    );
-    tests.add("SLOW NETWORK", test_slow_network, config.clone());
-    tests.add("FLAKY NETWORK", test_flaky_network, config.clone());
-    tests.add(
-        "FLAKY NETWORK ON START",
-        test_flaky_network_on_start,
-        config.clone(),
-    );
-    tests.add(
-        "FLAKY NETWORK AND CRASH",
-        test_flaky_network_and_crash,
-        config.clone(),
-    );
-    let mut rand = Pcg64::seed_from_u64(config.seed);
-    for run in 1..=args.monkeys {
-        let mut run_config = config.clone();
-        run_config.seed = rand.next_u64();
-        tests.add(
-            &format!("CHAOS MONKEY (run {})", run),
-            test_chaos_monkey,
-            run_config,
+    // in partial view mode members lists are not expected to be equal to the group,
+    // so only the overlay tests are run
+    if config.partial_view.is_some() {
+        add_test(&mut tests, "PARTIAL VIEW", test_partial_view, &config);
+        add_test(
+            &mut tests,
+            "PARTIAL VIEW CRASH",
+            test_partial_view_crash,
+            &config,
         );
+    } else {
+        add_test(&mut tests, "SIMPLE", test_simple, &config);
+        add_test(&mut tests, "RANDOM SEED", test_random_seed, &config);
+        add_test(&mut tests, "NODE JOIN", test_node_join, &config);
+        add_test(&mut tests, "NODE LEAVE", test_node_leave, &config);
+        add_test(&mut tests, "NODE CRASH", test_node_crash, &config);
+        add_test(&mut tests, "SEED NODE CRASH", test_seed_node_crash, &config);
+        add_test(
+            &mut tests,
+            "NODE CRASH RECOVER",
+            test_node_crash_recover,
+            &config,
+        );
+        if args.incarnations {
+            add_test(
+                &mut tests,
+                "NODE CRASH RECOVER RAPID",
+                test_node_rapid_crash_recover,
+                &config,
+            );
+            add_test(
+                &mut tests,
+                "NODE CRASH RECOVER FLAPPING",
+                test_node_flapping,
+                &config,
+            );
+            add_test(
+                &mut tests,
+                "NODE CRASH RECOVER STALE RUMOR",
+                test_node_recover_stale_rumor,
+                &config,
+            );
+        }
+        if args.rolling_restart {
+            add_test(&mut tests, "ROLLING RESTART", test_rolling_restart, &config);
+        }
+        add_test(&mut tests, "NODE OFFLINE", test_node_offline, &config);
+        add_test(
+            &mut tests,
+            "SEED NODE OFFLINE",
+            test_seed_node_offline,
+            &config,
+        );
+        add_test(
+            &mut tests,
+            "NODE OFFLINE RECOVER",
+            test_node_offline_recover,
+            &config,
+        );
+        add_test(
+            &mut tests,
+            "NODE CANNOT RECEIVE",
+            test_node_cannot_receive,
+            &config,
+        );
+        add_test(
+            &mut tests,
+            "NODE CANNOT SEND",
+            test_node_cannot_send,
+            &config,
+        );
+        add_test(
+            &mut tests,
+            "NETWORK PARTITION",
+            test_network_partition,
+            &config,
+        );
+        add_test(
+            &mut tests,
+            "NETWORK PARTITION RECOVER",
+            test_network_partition_recover,
+            &config,
+        );
+        if args.partitions {
+            add_test(
+                &mut tests,
+                "ONE-WAY PARTITION",
+                test_one_way_partition,
+                &config,
+            );
+            add_test(
+                &mut tests,
+                "THREE-WAY PARTITION",
+                test_three_way_partition,
+                &config,
+            );
+            add_test(&mut tests, "BRIDGE NODE", test_bridge_node, &config);
+        }
+        add_test(
+            &mut tests,
+            "TWO NODES CANNOT COMMUNICATE",
+            test_two_nodes_cannot_communicate,
+            &config,
+        );
+        add_test(&mut tests, "SLOW NETWORK", test_slow_network, &config);
+        add_test(&mut tests, "FLAKY NETWORK", test_flaky_network, &config);
+        add_test(
+            &mut tests,
+            "FLAKY NETWORK ON START",
+            test_flaky_network_on_start,
+            &config,
+        );
+        add_test(
+            &mut tests,
+            "FLAKY NETWORK AND CRASH",
+            test_flaky_network_and_crash,
+            &config,
+        );
+        if args.clocks {
+            add_test(&mut tests, "CLOCK DRIFT", test_clock_drift, &config);
+            add_test(&mut tests, "CLOCK JUMP", test_clock_jump, &config);
+        }
+        if args.churn {
+            add_test(&mut tests, "CHURN", test_churn, &config);
+        }
+        if args.detector {
+            add_test(&mut tests, "DETECTION TIME", test_detection_time, &config);
+            add_test(&mut tests, "FALSE POSITIVES", test_false_positives, &config);
+        }
+        if args.notifications {
+            add_test(&mut tests, "NOTIFICATIONS", test_notifications, &config);
+        }
+        let mut rand = Pcg64::seed_from_u64(config.seed);
+        for run in 1..=args.monkeys {
+            let name = format!("CHAOS MONKEY (run {})", run);
+            let mut run_config = with_timeseries(&config, &name);
+            run_config.seed = rand.next_u64();
+            tests.add(&name, test_chaos_monkey, run_config);
+        }
+        add_test(
+            &mut tests,
+            "SCALABILITY NORMAL",
+            test_scalability_normal,
+            &config,
+        );
+        add_test(
+            &mut tests,
+            "SCALABILITY CRASH",
+            test_scalability_crash,
+            &config,
+        );
+        if config.large_scale.is_some() {
+            add_test(
+                &mut tests,
+                "SCALABILITY LARGE",
+                test_scalability_large,
+                &config,
+            );
+        }
     }
-    tests.add(
-        "SCALABILITY NORMAL",
-        test_scalability_normal,
-        config.clone(),
-    );
-    tests.add("SCALABILITY CRASH", test_scalability_crash, config.clone());
 
     if args.test.is_none() {
         tests.run();
@@ -1,3 +1,4 @@
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::env;
 use std::io::Write;
@@ -13,7 +14,8 @@ use rand_pcg::Pcg64;
 use serde::{Deserialize, Serialize};
 use sugars::{rc, refcell};
 
-use dslib::pynode::{JsonMessage, PyNodeFactory};
+use dslib::node::{Context, LocalEvent, LocalEventType, Node};
+use dslib::pynode::{JsonMessage, PyNode, PyNodeFactory};
 use dslib::system::System;
 use dslib::test::{TestResult, TestSuite};
 
@@ -53,6 +55,64 @@ struct DeleteRespMessage<'a> {
     value: Option<&'a str>,
 }
 
+#[derive(Serialize)]
+struct MGetMessage<'a> {
+    keys: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct MGetRespMessage {
+    values: HashMap<String, Option<String>>,
+}
+
+#[derive(Serialize)]
+struct MPutMessage<'a> {
+    items: &'a HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct MPutRespMessage {
+    keys: HashSet<String>,
+}
+
+#[derive(Serialize)]
+struct TransferMessage<'a> {
+    from: &'a str,
+    to: &'a str,
+    amount: u64,
+}
+
+#[derive(Deserialize)]
+struct TransferRespMessage {
+    from: String,
+    to: String,
+    amount: u64,
+    committed: bool,
+}
+
+#[derive(Serialize)]
+struct TxReadMessage<'a> {
+    keys: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct TxReadRespMessage {
+    values: HashMap<String, Option<String>>,
+}
+
+#[derive(Serialize)]
+struct ScanMessage<'a> {
+    start: &'a str,
+    end: &'a str,
+    limit: usize,
+}
+
+#[derive(Deserialize)]
+struct ScanRespMessage {
+    items: Vec<(String, String)>,
+    next: Option<String>,
+}
+
 #[derive(Serialize)]
 struct DumpKeysMessage {}
 
@@ -72,11 +132,27 @@ struct CountRecordsRespMessage {
 #[derive(Serialize)]
 struct NodeAddedMessage<'a> {
     id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weight: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epoch: Option<u64>,
 }
 
 #[derive(Serialize)]
 struct NodeRemovedMessage<'a> {
     id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epoch: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct MembershipChangeMessage {
+    epoch: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct RebalanceDoneMessage {
+    epoch: u64,
 }
 
 // UTILS ---------------------------------------------------------------------------------------------------------------
@@ -86,6 +162,12 @@ struct TestConfig<'a> {
     node_factory: &'a PyNodeFactory,
     node_count: u32,
     seed: u64,
+    weighted: bool,
+    max_rebalance_messages: Option<f64>,
+    max_rebalance_traffic: f64,
+    zipf_exponent: f64,
+    max_load_ratio: Option<f64>,
+    rebalance_done: bool,
 }
 
 fn init_logger(level: LevelFilter) {
@@ -103,13 +185,11 @@ fn build_system(config: &TestConfig, measure_max_size: bool) -> System<JsonMessa
         node_ids.push(format!("{}", n));
     }
     for node_id in node_ids.iter() {
-        let mut node = config
-            .node_factory
-            .build(node_id, (node_id, node_ids.clone()), config.seed);
+        let mut node = build_node(node_id, node_ids.clone(), config);
         if measure_max_size {
             node.set_max_size_freq(1000000);
         }
-        sys.add_node(rc!(refcell!(node)));
+        sys.add_node(rc!(refcell!(TracedNode { node })));
     }
     return sys;
 }
@@ -120,10 +200,77 @@ fn add_node(node_id: &str, sys: &mut System<JsonMessage>, config: &TestConfig) {
         node_ids.push(id);
     }
     node_ids.push(node_id.to_string());
-    let node = config
-        .node_factory
-        .build(node_id, (node_id, node_ids), config.seed);
-    sys.add_node(rc!(refcell!(node)));
+    let node = build_node(node_id, node_ids, config);
+    sys.add_node(rc!(refcell!(TracedNode { node })));
+}
+
+// in weighted mode nodes also receive weights of all nodes as the third argument
+fn build_node(node_id: &str, node_ids: Vec<String>, config: &TestConfig) -> PyNode {
+    if config.weighted {
+        let weights: HashMap<String, f64> = node_ids
+            .iter()
+            .map(|id| (id.clone(), node_weight(id, config)))
+            .collect();
+        config
+            .node_factory
+            .build(node_id, (node_id, node_ids, weights), config.seed)
+    } else {
+        config
+            .node_factory
+            .build(node_id, (node_id, node_ids), config.seed)
+    }
+}
+
+thread_local! {
+    // messages received by nodes from other nodes (with receiver ids) while routing is traced
+    static ROUTING_TRACE: RefCell<Option<Vec<(String, JsonMessage)>>> = const { RefCell::new(None) };
+}
+
+// node wrapper recording received messages into the routing trace when it is enabled
+struct TracedNode {
+    node: PyNode,
+}
+
+impl Node<JsonMessage> for TracedNode {
+    fn id(&self) -> &String {
+        self.node.id()
+    }
+
+    fn on_message(&mut self, msg: JsonMessage, from: String, ctx: &mut Context<JsonMessage>) {
+        ROUTING_TRACE.with(|trace| {
+            if let Some(trace) = trace.borrow_mut().as_mut() {
+                trace.push((self.node.id().clone(), msg.clone()));
+            }
+        });
+        self.node.on_message(msg, from, ctx);
+    }
+
+    fn on_local_message(&mut self, msg: JsonMessage, ctx: &mut Context<JsonMessage>) {
+        self.node.on_local_message(msg, ctx);
+    }
+
+    fn on_timer(&mut self, timer: String, ctx: &mut Context<JsonMessage>) {
+        self.node.on_timer(timer, ctx);
+    }
+
+    fn max_size(&mut self) -> u64 {
+        self.node.max_size()
+    }
+}
+
+// relative capacity of the node, all nodes are identical unless weighted mode is enabled
+fn node_weight(node_id: &str, config: &TestConfig) -> f64 {
+    if config.weighted {
+        NODE_WEIGHTS[node_id.parse::<usize>().unwrap() % NODE_WEIGHTS.len()]
+    } else {
+        1.
+    }
+}
+
+// expected fraction of all keys stored on the node
+fn weight_share(node_id: &str, nodes: &[String], config: &TestConfig) -> f64 {
+    let total_weight: f64 = nodes.iter().map(|id| node_weight(id, config)).sum();
+    node_weight(node_id, config) / total_weight
 }
 
 fn check_get(
@@ -134,7 +281,7 @@ fn check_get(
     max_steps: u32,
 ) -> TestResult {
     sys.send_local(JsonMessage::from("GET", &GetMessage { key }), node);
-    let res = sys.step_until_local_message_max_steps(node, max_steps);
+    let res = step_until_response(sys, node, max_steps);
     assume!(res.is_ok(), format!("GET_RESP is not returned by {}", node))?;
     let msgs = res.unwrap();
     let msg = msgs.first().unwrap();
@@ -153,7 +300,7 @@ fn check_put(
     max_steps: u32,
 ) -> TestResult {
     sys.send_local(JsonMessage::from("PUT", &PutMessage { key, value }), node);
-    let res = sys.step_until_local_message_max_steps(node, max_steps);
+    let res = step_until_response(sys, node, max_steps);
     assume!(res.is_ok(), format!("PUT_RESP is not returned by {}", node))?;
     let msgs = res.unwrap();
     let msg = msgs.first().unwrap();
@@ -172,7 +319,7 @@ fn check_delete(
     max_steps: u32,
 ) -> TestResult {
     sys.send_local(JsonMessage::from("DELETE", &DeleteMessage { key }), node);
-    let res = sys.step_until_local_message_max_steps(node, max_steps);
+    let res = step_until_response(sys, node, max_steps);
     assume!(
         res.is_ok(),
         format!("DELETE_RESP is not returned by {}", node)
@@ -188,7 +335,7 @@ fn check_delete(
 
 fn dump_keys(sys: &mut System<JsonMessage>, node: &str) -> Result<HashSet<String>, String> {
     sys.send_local(JsonMessage::from("DUMP_KEYS", &DumpKeysMessage {}), node);
-    let res = sys.step_until_local_message_max_steps(node, 100);
+    let res = step_until_response(sys, node, 100);
     assume!(
         res.is_ok(),
         format!("DUMP_KEYS_RESP is not returned by {}", node)
@@ -210,12 +357,23 @@ fn key_distribution(
     Ok(dist)
 }
 
+// returns owner node of each key
+fn key_owners(sys: &mut System<JsonMessage>) -> Result<HashMap<String, String>, String> {
+    let mut owners = HashMap::new();
+    for (node, keys) in key_distribution(sys)? {
+        for k in keys {
+            owners.insert(k, node.clone());
+        }
+    }
+    Ok(owners)
+}
+
 fn count_records(sys: &mut System<JsonMessage>, node: &str) -> Result<u64, String> {
     sys.send_local(
         JsonMessage::from("COUNT_RECORDS", &CountRecordsMessage {}),
         node,
     );
-    let res = sys.step_until_local_message_max_steps(node, 100);
+    let res = step_until_response(sys, node, 100);
     assume!(
         res.is_ok(),
         format!("COUNT_RECORDS_RESP is not returned by {}", node)
@@ -227,31 +385,194 @@ fn count_records(sys: &mut System<JsonMessage>, node: &str) -> Result<u64, Strin
     Ok(data.count)
 }
 
-fn send_node_added(sys: &mut System<JsonMessage>, added: &str) {
+// membership changes are numbered by the epoch counter kept by the test
+fn send_node_added(
+    sys: &mut System<JsonMessage>,
+    added: &str,
+    epoch: &mut u64,
+    config: &TestConfig,
+) {
+    let weight = if config.weighted {
+        Some(node_weight(added, config))
+    } else {
+        None
+    };
+    let epoch = next_epoch(epoch, config);
     for node in sys.get_node_ids() {
         sys.send_local(
-            JsonMessage::from("NODE_ADDED", &NodeAddedMessage { id: added }),
+            JsonMessage::from(
+                "NODE_ADDED",
+                &NodeAddedMessage {
+                    id: added,
+                    weight,
+                    epoch,
+                },
+            ),
             &node,
         );
     }
 }
 
-fn send_node_removed(sys: &mut System<JsonMessage>, removed: &str) {
+fn send_node_removed(
+    sys: &mut System<JsonMessage>,
+    removed: &str,
+    epoch: &mut u64,
+    config: &TestConfig,
+) {
+    let epoch = next_epoch(epoch, config);
     for node in sys.get_node_ids() {
         sys.send_local(
-            JsonMessage::from("NODE_REMOVED", &NodeRemovedMessage { id: removed }),
+            JsonMessage::from("NODE_REMOVED", &NodeRemovedMessage { id: removed, epoch }),
             &node,
         );
     }
 }
 
+// returns times and epochs of membership change notifications received by the node
+// (or REBALANCE_DONE messages sent by it) from the given local events
+fn message_epochs(events: &[LocalEvent<JsonMessage>], sent: bool) -> Vec<(f64, u64)> {
+    events
+        .iter()
+        .filter_map(|e| {
+            let msg = e.msg.as_ref()?;
+            let epoch = match e.tip {
+                LocalEventType::LocalMessageSend if sent && msg.tip == "REBALANCE_DONE" => {
+                    serde_json::from_str::<RebalanceDoneMessage>(&msg.data)
+                        .ok()?
+                        .epoch
+                }
+                LocalEventType::LocalMessageReceive
+                    if !sent && (msg.tip == "NODE_ADDED" || msg.tip == "NODE_REMOVED") =>
+                {
+                    serde_json::from_str::<MembershipChangeMessage>(&msg.data)
+                        .ok()?
+                        .epoch?
+                }
+                _ => return None,
+            };
+            Some((e.time, epoch))
+        })
+        .collect()
+}
+
+// advances the epoch counter, epochs are sent to nodes only in rebalance completion mode
+fn next_epoch(epoch: &mut u64, config: &TestConfig) -> Option<u64> {
+    *epoch += 1;
+    if config.rebalance_done {
+        Some(*epoch)
+    } else {
+        None
+    }
+}
+
+// waits for a response to local request skipping REBALANCE_DONE messages,
+// which are checked separately using the history of local events
+fn step_until_response(
+    sys: &mut System<JsonMessage>,
+    node: &str,
+    max_steps: u32,
+) -> Result<Vec<JsonMessage>, String> {
+    loop {
+        let msgs: Vec<JsonMessage> = sys
+            .step_until_local_message_max_steps(node, max_steps)
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .filter(|msg| msg.tip != "REBALANCE_DONE")
+            .collect();
+        if !msgs.is_empty() {
+            return Ok(msgs);
+        }
+    }
+}
+
+// waits for REBALANCE_DONE with the epoch of the last membership change from each node
+// and checks that no keys are in flight after that
+fn step_until_rebalanced(
+    sys: &mut System<JsonMessage>,
+    nodes: &[String],
+    expected_keys: u64,
+    max_steps: u32,
+) -> TestResult {
+    // rebalance starts with the last membership change notification
+    let mut expected_epochs = HashMap::new();
+    let mut start_time: f64 = 0.;
+    for node in nodes {
+        if let Some((time, epoch)) = message_epochs(&sys.get_local_events(node), false).last() {
+            expected_epochs.insert(node.clone(), *epoch);
+            start_time = start_time.max(*time);
+        }
+    }
+
+    // step one event at a time to stop right after the last REBALANCE_DONE,
+    // only the events added since the previous step are scanned
+    let mut done_times = HashMap::new();
+    let mut scanned_events: HashMap<&String, usize> = HashMap::new();
+    let mut steps = 0;
+    while done_times.len() < expected_epochs.len() && steps < max_steps && sys.step() {
+        steps += 1;
+        for (node, expected_epoch) in expected_epochs.iter() {
+            if done_times.contains_key(node) {
+                continue;
+            }
+            let events = sys.get_local_events(node);
+            let from = scanned_events.insert(node, events.len()).unwrap_or(0);
+            let done = message_epochs(&events[from..], true)
+                .into_iter()
+                .find(|(_, epoch)| epoch == expected_epoch);
+            if let Some((time, _)) = done {
+                done_times.insert(node.clone(), time);
+            }
+        }
+    }
+    let mut pending: Vec<&String> = expected_epochs
+        .keys()
+        .filter(|node| !done_times.contains_key(*node))
+        .collect();
+    pending.sort();
+    assume!(
+        pending.is_empty(),
+        format!("REBALANCE_DONE is not returned by {:?}", pending)
+    )?;
+
+    // all keys must be already delivered to their new owners,
+    // counting records only processes local requests, so if any message is delivered
+    // to some node meanwhile then rebalancing was still in progress
+    let all_nodes = sys.get_node_ids();
+    let received_count = |sys: &System<JsonMessage>| -> u64 {
+        all_nodes
+            .iter()
+            .map(|node| sys.get_received_message_count(node))
+            .sum()
+    };
+    let init_received_count = received_count(sys);
+    let mut total_count = 0;
+    for node in nodes.iter() {
+        total_count += count_records(sys, node)?;
+    }
+    let delivered_count = received_count(sys) - init_received_count;
+    assume!(
+        total_count == expected_keys && delivered_count == 0,
+        format!(
+            "Keys are in flight after REBALANCE_DONE (keys observed = {}, expected = {}, messages delivered = {})",
+            total_count, expected_keys, delivered_count
+        )
+    )?;
+    let end_time = done_times.values().cloned().fold(start_time, f64::max);
+    println!("Rebalance duration: {:.3}", end_time - start_time);
+    Ok(true)
+}
+
 fn step_until_stabilized(
     sys: &mut System<JsonMessage>,
     nodes: &Vec<String>,
     expected_keys: u64,
     steps_per_iter: u32,
     max_steps: u32,
+    config: &TestConfig,
 ) -> TestResult {
+    if config.rebalance_done {
+        return step_until_rebalanced(sys, nodes, expected_keys, max_steps);
+    }
     let mut stabilized = false;
     let mut steps = 0;
     let mut counts = HashMap::new();
@@ -295,6 +616,7 @@ fn check(
     expected: &HashMap<String, String>,
     check_values: bool,
     check_distribution: bool,
+    config: &TestConfig,
 ) -> TestResult {
     let mut stored_keys = HashSet::new();
     let mut node_key_counts = Vec::new();
@@ -329,14 +651,18 @@ fn check(
         println!("OK")
     }
 
-    // check keys distribution
+    // check keys distribution (proportional to node weights)
     if check_distribution {
-        let target_count = (expected.len() as f64 / node_key_counts.len() as f64).round();
+        let target_counts: Vec<f64> = nodes
+            .iter()
+            .map(|node| (expected.len() as f64 * weight_share(node, nodes, config)).round())
+            .collect();
         let max_count = *node_key_counts.iter().max().unwrap();
         let min_count = *node_key_counts.iter().min().unwrap();
         let deviations: Vec<f64> = node_key_counts
             .iter()
-            .map(|x| (target_count - *x as f64).abs() / target_count)
+            .zip(target_counts.iter())
+            .map(|(x, target_count)| (target_count - *x as f64).abs() / target_count)
             .collect();
         let avg_deviation = deviations.iter().sum::<f64>() / node_key_counts.len() as f64;
         let max_deviation = deviations
@@ -345,7 +671,14 @@ fn check(
             .max()
             .unwrap();
         println!("\nStored keys per node:");
-        println!("  - target: {}", target_count);
+        if config.weighted {
+            println!(
+                "  - target per unit of weight: {}",
+                target_counts[0] / node_weight(&nodes[0], config)
+            );
+        } else {
+            println!("  - target: {}", target_counts[0]);
+        }
         println!("  - min: {}", min_count);
         println!("  - max: {}", max_count);
         println!("  - average deviation from target: {:.3}", avg_deviation);
@@ -376,10 +709,18 @@ fn check_moved_keys(
         total_count += b.len() as u64;
     }
     let moved_count = total_count - not_moved_count;
-    let deviation = (moved_count as f64 - target as f64) / target as f64;
     println!("\nMoved keys:");
     println!("  - target: {}", target);
     println!("  - observed: {}", moved_count);
+    // no keys should move if the set of nodes has not changed
+    if target == 0 {
+        return assume_eq!(
+            moved_count,
+            0,
+            format!("Keys are moved while no nodes are changed")
+        );
+    }
+    let deviation = (moved_count as f64 - target as f64) / target as f64;
     println!("  - deviation: {:.3}", deviation);
     assume!(
         deviation <= 0.1,
@@ -387,75 +728,608 @@ fn check_moved_keys(
     )
 }
 
-const SYMBOLS: [char; 36] = [
-    'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's',
-    't', 'u', 'v', 'w', 'x', 'y', 'z', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9',
-];
-const WEIGHTS: [usize; 36] = [
-    13, 16, 3, 8, 8, 5, 6, 23, 4, 8, 24, 12, 2, 1, 1, 10, 5, 8, 10, 1, 24, 3, 1, 8, 12, 22, 5, 20,
-    18, 5, 5, 2, 1, 3, 16, 22,
-];
+// performs random requests from random nodes measuring the number of network messages
+// and forwards (nodes other than the entry node touched by the request) per request,
+// only messages mentioning the requested key are attributed to the request
+fn check_routing(
+    sys: &mut System<JsonMessage>,
+    nodes: &[String],
+    kv: &mut HashMap<String, String>,
+    request_count: u32,
+    rand: &mut Pcg64,
+) -> TestResult {
+    let keys = sorted_keys(kv);
+    let mut total_messages = 0;
+    let mut total_forwards = 0;
+    let mut max_messages = 0;
+    let mut max_forwards = 0;
+    for _ in 0..request_count {
+        let node = nodes.choose(rand).unwrap();
+        let k = keys.choose(rand).unwrap();
+        ROUTING_TRACE.with(|trace| trace.replace(Some(Vec::new())));
+        let res = if rand.gen_bool(0.5) {
+            check_get(sys, node, k, Some(&kv[k]), 100)
+        } else {
+            let v = random_string(8, rand);
+            let res = check_put(sys, node, k, &v, 100);
+            kv.insert(k.clone(), v);
+            res
+        };
+        let trace = ROUTING_TRACE.with(|trace| trace.replace(None)).unwrap();
+        res?;
+        let linked: Vec<&String> = trace
+            .iter()
+            .filter(|(_, msg)| msg.tip.contains(k.as_str()) || msg.data.contains(k.as_str()))
+            .map(|(to, _)| to)
+            .collect();
+        let message_count = linked.len();
+        let forwards = linked
+            .iter()
+            .filter(|to| **to != node)
+            .collect::<HashSet<_>>()
+            .len();
+        total_messages += message_count;
+        total_forwards += forwards;
+        max_messages = max_messages.max(message_count);
+        max_forwards = max_forwards.max(forwards);
+    }
+    println!("\nRouting (N = {}):", nodes.len());
+    println!(
+        "  - messages per request: avg {:.2}, max {}",
+        total_messages as f64 / request_count as f64,
+        max_messages
+    );
+    println!(
+        "  - forwards per request: avg {:.2}, max {}",
+        total_forwards as f64 / request_count as f64,
+        max_forwards
+    );
+    assume!(
+        max_forwards <= 1,
+        format!(
+            "Request is forwarded {} times (expected at most one forward to the owner)",
+            max_forwards
+        )
+    )?;
+    Ok(true)
+}
 
-fn random_string(length: usize, rand: &mut Pcg64) -> String {
-    let dist = WeightedIndex::new(&WEIGHTS).unwrap();
-    rand.sample_iter(&dist)
-        .take(length)
-        .map(|x| SYMBOLS[x])
-        .collect()
+// compares the network cost of rebalancing with the minimal cost of moving each moved key once
+// directly from its old owner to the new one (one message with serialized moved records
+// per pair of such nodes)
+fn check_rebalance_cost(
+    before: &HashMap<String, HashSet<String>>,
+    after: &HashMap<String, HashSet<String>>,
+    kv: &HashMap<String, String>,
+    message_count: u64,
+    traffic: u64,
+    config: &TestConfig,
+) -> TestResult {
+    let mut owners = HashMap::new();
+    for (node, keys) in after.iter() {
+        for k in keys {
+            owners.insert(k, node);
+        }
+    }
+    let mut transfers: HashMap<(&String, &String), HashMap<&String, &String>> = HashMap::new();
+    for (node, keys) in before.iter() {
+        for k in keys {
+            if let Some(owner) = owners.get(k) {
+                if *owner != node {
+                    transfers
+                        .entry((node, *owner))
+                        .or_default()
+                        .insert(k, &kv[k]);
+                }
+            }
+        }
+    }
+    let min_message_count = transfers.len() as u64;
+    let min_traffic: u64 = transfers
+        .values()
+        .map(|records| serde_json::to_string(records).unwrap().len() as u64)
+        .sum();
+    let message_ratio = message_count as f64 / min_message_count.max(1) as f64;
+    let traffic_ratio = traffic as f64 / min_traffic.max(1) as f64;
+    println!("\nRebalancing cost:");
+    println!(
+        "  - messages: {} (min {})",
+        message_count, min_message_count
+    );
+    println!("  - traffic: {} (min {})", traffic, min_traffic);
+    println!("  - messages / min messages: {:.3}", message_ratio);
+    println!("  - traffic / min traffic: {:.3}", traffic_ratio);
+    if let Some(max_ratio) = config.max_rebalance_messages {
+        assume!(
+            message_ratio <= max_ratio,
+            format!(
+                "Rebalancing uses {:.1} times more messages than the minimal (probably you send keys to all nodes, move them via other nodes or one by one)",
+                message_ratio
+            )
+        )?;
+    }
+    assume!(
+        traffic_ratio <= config.max_rebalance_traffic,
+        format!(
+            "Rebalancing traffic is {:.1} times above the minimal (probably you send keys which are not moved or move them via other nodes)",
+            traffic_ratio
+        )
+    )?;
+    Ok(true)
 }
 
-// TESTS ---------------------------------------------------------------------------------------------------------------
+// returns keys in a fixed order, so that random choice of keys is reproducible with the same seed
+fn sorted_keys(kv: &HashMap<String, String>) -> Vec<String> {
+    let mut keys: Vec<String> = kv.keys().cloned().collect();
+    keys.sort();
+    keys
+}
 
-fn test_single_node(config: &TestConfig) -> TestResult {
-    let mut sys = build_system(config, false);
-    let mut rand = Pcg64::seed_from_u64(config.seed);
+// performs random operations from random nodes (e.g. while rebalancing is in progress)
+// checking that every GET returns the latest acknowledged value and no key disappears
+fn check_random_ops(
+    sys: &mut System<JsonMessage>,
+    nodes: &[String],
+    kv: &mut HashMap<String, String>,
+    op_count: u32,
+    rand: &mut Pcg64,
+) -> TestResult {
+    for _ in 0..op_count {
+        let node = nodes.choose(rand).unwrap();
+        let op = rand.gen_range(0..10);
+        if op < 5 && !kv.is_empty() {
+            let k = sorted_keys(kv).choose(rand).unwrap().clone();
+            check_get(sys, node, &k, kv.get(&k).map(|v| v.as_str()), 1000)?;
+        } else if op < 9 || kv.is_empty() {
+            let k = if rand.gen_bool(0.5) && !kv.is_empty() {
+                sorted_keys(kv).choose(rand).unwrap().clone()
+            } else {
+                random_string(8, rand).to_uppercase()
+            };
+            let v = random_string(8, rand);
+            check_put(sys, node, &k, &v, 1000)?;
+            kv.insert(k, v);
+        } else {
+            let k = sorted_keys(kv).choose(rand).unwrap().clone();
+            let v = kv.remove(&k);
+            check_delete(sys, node, &k, v.as_deref(), 1000)?;
+        }
+    }
+    Ok(true)
+}
 
-    let node = "0";
-    let key = random_string(8, &mut rand).to_uppercase();
-    let value = random_string(8, &mut rand);
-    let max_steps = 10;
+// weights of nodes in weighted mode (assigned cyclically by node id)
+const NODE_WEIGHTS: [f64; 3] = [1., 2., 4.];
 
-    check_get(&mut sys, node, &key, None, max_steps)?;
-    check_put(&mut sys, node, &key, &value, max_steps)?;
-    check_get(&mut sys, node, &key, Some(&value), max_steps)?;
-    check_delete(&mut sys, node, &key, Some(&value), max_steps)?;
-    check_get(&mut sys, node, &key, None, max_steps)?;
-    check_delete(&mut sys, node, &key, None, max_steps)
+fn check_mget(
+    sys: &mut System<JsonMessage>,
+    node: &str,
+    keys: &[String],
+    expected: &HashMap<String, String>,
+) -> TestResult {
+    sys.send_local(JsonMessage::from("MGET", &MGetMessage { keys }), node);
+    let res = step_until_response(sys, node, 1000);
+    assume!(
+        res.is_ok(),
+        format!("MGET_RESP is not returned by {}", node)
+    )?;
+    let msgs = res.unwrap();
+    let msg = msgs.first().unwrap();
+    assume_eq!(msg.tip, "MGET_RESP")?;
+    let data: MGetRespMessage = serde_json::from_str(&msg.data).unwrap();
+    let expected_values: HashMap<String, Option<String>> = keys
+        .iter()
+        .map(|k| (k.clone(), expected.get(k).cloned()))
+        .collect();
+    assume_eq!(data.values, expected_values)?;
+    Ok(true)
 }
 
-fn test_inserts(config: &TestConfig) -> TestResult {
-    let mut sys = build_system(config, false);
-    let mut rand = Pcg64::seed_from_u64(config.seed);
+fn check_mput(
+    sys: &mut System<JsonMessage>,
+    node: &str,
+    items: &HashMap<String, String>,
+) -> TestResult {
+    sys.send_local(JsonMessage::from("MPUT", &MPutMessage { items }), node);
+    let res = step_until_response(sys, node, 1000);
+    assume!(
+        res.is_ok(),
+        format!("MPUT_RESP is not returned by {}", node)
+    )?;
+    let msgs = res.unwrap();
+    let msg = msgs.first().unwrap();
+    assume_eq!(msg.tip, "MPUT_RESP")?;
+    let data: MPutRespMessage = serde_json::from_str(&msg.data).unwrap();
+    let expected_keys: HashSet<String> = items.keys().cloned().collect();
+    assume_eq!(data.keys, expected_keys)?;
+    Ok(true)
+}
 
-    // insert random key-value pairs from each node
-    let mut kv = HashMap::new();
-    for node in sys.get_node_ids() {
-        let k = random_string(8, &mut rand).to_uppercase();
-        let v = random_string(8, &mut rand);
-        check_put(&mut sys, &node, &k, &v, 100)?;
-        kv.insert(k, v);
+// checks that the number of network messages used by each batch operation is bounded
+// by the number of distinct nodes storing its keys (a request and a response per node)
+fn check_batch_messages(
+    batches: &[(Vec<String>, u64)],
+    dist: &HashMap<String, HashSet<String>>,
+) -> TestResult {
+    let mut owners = HashMap::new();
+    for (node, keys) in dist.iter() {
+        for k in keys {
+            owners.insert(k, node);
+        }
     }
-
-    // check that all key-values can be read from each node
-    let nodes = sys.get_node_ids();
-    check(&mut sys, &nodes, &kv, true, false)
+    let mut total_messages = 0;
+    let mut total_keys = 0;
+    let mut total_owners = 0;
+    for (keys, message_count) in batches.iter() {
+        let batch_owners: HashSet<&String> =
+            keys.iter().filter_map(|k| owners.get(k).copied()).collect();
+        // owners of missing keys are unknown, so each of them is counted as a separate node
+        let missing = keys.iter().filter(|k| !owners.contains_key(k)).count();
+        assume!(
+            *message_count <= 2 * (batch_owners.len() + missing) as u64,
+            format!(
+                "Batch of {} keys stored on {} nodes used {} messages (probably you send a request per key)",
+                keys.len(),
+                batch_owners.len(),
+                message_count
+            )
+        )?;
+        total_messages += message_count;
+        total_keys += keys.len();
+        total_owners += batch_owners.len();
+    }
+    println!("\nMessages per batch:");
+    println!("  - keys: {:.2}", total_keys as f64 / batches.len() as f64);
+    println!(
+        "  - owner nodes: {:.2}",
+        total_owners as f64 / batches.len() as f64
+    );
+    println!(
+        "  - messages: {:.2}",
+        total_messages as f64 / batches.len() as f64
+    );
+    Ok(true)
 }
 
-fn test_deletes(config: &TestConfig) -> TestResult {
-    let mut sys = build_system(config, false);
-    let mut rand = Pcg64::seed_from_u64(config.seed);
-    let mut kv = HashMap::new();
+// creates accounts with initial balance (optionally avoiding the given node)
+// and returns their balances
+fn create_accounts(
+    sys: &mut System<JsonMessage>,
+    count: usize,
+    excluded: Option<&str>,
+    rand: &mut Pcg64,
+) -> Result<HashMap<String, i64>, String> {
+    let mut balances = HashMap::new();
+    for _ in 0..count {
+        let k = random_string(8, rand).to_uppercase();
+        let v = INITIAL_BALANCE.to_string();
+        let node = sys.get_node_ids().choose(rand).unwrap().clone();
+        check_put(sys, &node, &k, &v, 100)?;
+        balances.insert(k, INITIAL_BALANCE);
+    }
+    if let Some(excluded) = excluded {
+        let mut excluded_keys: Vec<String> = dump_keys(sys, excluded)?.into_iter().collect();
+        excluded_keys.sort();
+        for k in excluded_keys {
+            let node = sys.get_node_ids().choose(rand).unwrap().clone();
+            check_delete(sys, &node, &k, Some(&INITIAL_BALANCE.to_string()), 100)?;
+            balances.remove(&k);
+        }
+    }
+    Ok(balances)
+}
 
-    // insert random key-value pairs from each node
-    for node in sys.get_node_ids() {
-        let k = random_string(8, &mut rand).to_uppercase();
-        let v = random_string(8, &mut rand);
-        check_put(&mut sys, &node, &k, &v, 100)?;
-        kv.insert(k, v);
+// picks a pair of accounts stored on different nodes
+fn random_transfer(
+    owners: &HashMap<String, String>,
+    rand: &mut Pcg64,
+) -> Result<(String, String), String> {
+    let mut accounts: Vec<&String> = owners.keys().collect();
+    accounts.sort();
+    let distinct_owners: HashSet<&String> = owners.values().collect();
+    assume!(
+        distinct_owners.len() > 1,
+        "All accounts are stored on a single node, cross-shard transfer is impossible"
+    )?;
+    loop {
+        let from = *accounts.choose(rand).unwrap();
+        let to = *accounts.choose(rand).unwrap();
+        if owners[from] != owners[to] {
+            return Ok((from.clone(), to.clone()));
+        }
     }
+}
 
-    // delete each key from one node and check that key is not present from another
-    for (k, v) in kv.iter() {
+fn send_transfer(sys: &mut System<JsonMessage>, node: &str, from: &str, to: &str, amount: u64) {
+    sys.send_local(
+        JsonMessage::from("TRANSFER", &TransferMessage { from, to, amount }),
+        node,
+    );
+}
+
+// waits for transfer result and applies it to the expected balances
+fn check_transfer_resp(
+    sys: &mut System<JsonMessage>,
+    node: &str,
+    balances: &mut HashMap<String, i64>,
+    max_steps: u32,
+) -> Result<bool, String> {
+    let res = step_until_response(sys, node, max_steps);
+    assume!(
+        res.is_ok(),
+        format!("TRANSFER_RESP is not returned by {}", node)
+    )?;
+    let msgs = res.unwrap();
+    let msg = msgs.first().unwrap();
+    assume_eq!(msg.tip, "TRANSFER_RESP")?;
+    let data: TransferRespMessage = serde_json::from_str(&msg.data).unwrap();
+    if data.committed {
+        *balances.get_mut(&data.from).unwrap() -= data.amount as i64;
+        *balances.get_mut(&data.to).unwrap() += data.amount as i64;
+    }
+    Ok(data.committed)
+}
+
+// reads all accounts in a single read-only transaction
+// and checks that their total balance is preserved
+fn check_total_balance(
+    sys: &mut System<JsonMessage>,
+    node: &str,
+    accounts: &[String],
+    max_steps: u32,
+) -> Result<HashMap<String, i64>, String> {
+    sys.send_local(
+        JsonMessage::from("TX_READ", &TxReadMessage { keys: accounts }),
+        node,
+    );
+    let res = step_until_response(sys, node, max_steps);
+    assume!(
+        res.is_ok(),
+        format!("TX_READ_RESP is not returned by {}", node)
+    )?;
+    let msgs = res.unwrap();
+    let msg = msgs.first().unwrap();
+    assume_eq!(msg.tip, "TX_READ_RESP")?;
+    let data: TxReadRespMessage = serde_json::from_str(&msg.data).unwrap();
+    let mut balances = HashMap::new();
+    for k in accounts {
+        let value = data.values.get(k).cloned().flatten();
+        assume!(value.is_some(), format!("Account {} is not found", k))?;
+        let balance = value.unwrap().parse::<i64>().unwrap();
+        assume!(
+            balance >= 0,
+            format!("Account {} has negative balance {}", k, balance)
+        )?;
+        balances.insert(k.clone(), balance);
+    }
+    let total: i64 = balances.values().sum();
+    let expected_total = INITIAL_BALANCE * accounts.len() as i64;
+    assume!(
+        total == expected_total,
+        format!(
+            "Total balance is {} instead of {} (transfer is observed partially)",
+            total, expected_total
+        )
+    )?;
+    Ok(balances)
+}
+
+// distribution of key popularity where the i-th most popular key is requested with probability ~ 1/i^s
+fn zipf_distribution(key_count: usize, exponent: f64) -> WeightedIndex<f64> {
+    let weights: Vec<f64> = (1..=key_count)
+        .map(|i| 1. / (i as f64).powf(exponent))
+        .collect();
+    WeightedIndex::new(&weights).unwrap()
+}
+
+// prints per-node load statistics and returns the ratio of max load to average load
+fn report_load(name: &str, loads: &HashMap<String, u64>) -> f64 {
+    let max_load = *loads.values().max().unwrap();
+    let min_load = *loads.values().min().unwrap();
+    let avg_load = loads.values().sum::<u64>() as f64 / loads.len() as f64;
+    let mut hot_nodes: Vec<&String> = loads
+        .iter()
+        .filter(|(_, load)| **load as f64 > 2. * avg_load)
+        .map(|(node, _)| node)
+        .collect();
+    hot_nodes.sort();
+    println!("\n{} per node:", name);
+    println!("  - average: {:.1}", avg_load);
+    println!("  - min: {}", min_load);
+    println!("  - max: {}", max_load);
+    println!(
+        "  - max/min ratio: {:.2}",
+        max_load as f64 / min_load.max(1) as f64
+    );
+    println!("  - max/average ratio: {:.2}", max_load as f64 / avg_load);
+    if !hot_nodes.is_empty() {
+        let hot_nodes: Vec<&str> = hot_nodes.iter().map(|x| x.as_str()).collect();
+        println!(
+            "  - hot nodes (load > 2x average): {}",
+            hot_nodes.join(", ")
+        );
+    }
+    max_load as f64 / avg_load
+}
+
+// returns a single page of records with keys in [start, end) and the key to continue from
+fn scan(
+    sys: &mut System<JsonMessage>,
+    node: &str,
+    start: &str,
+    end: &str,
+    limit: usize,
+) -> Result<ScanRespMessage, String> {
+    sys.send_local(
+        JsonMessage::from("SCAN", &ScanMessage { start, end, limit }),
+        node,
+    );
+    let res = step_until_response(sys, node, 1000);
+    assume!(
+        res.is_ok(),
+        format!("SCAN_RESP is not returned by {}", node)
+    )?;
+    let msgs = res.unwrap();
+    let msg = msgs.first().unwrap();
+    assume_eq!(msg.tip, "SCAN_RESP")?;
+    let data: ScanRespMessage = serde_json::from_str(&msg.data).unwrap();
+    Ok(data)
+}
+
+// checks a single page of scan results and returns the key to continue from
+fn check_scan_page(
+    sys: &mut System<JsonMessage>,
+    node: &str,
+    start: &str,
+    end: &str,
+    limit: usize,
+    expected: &[(&String, &String)],
+) -> Result<Option<String>, String> {
+    let ScanRespMessage { items, next } = scan(sys, node, start, end, limit)?;
+    assume!(
+        items.len() <= limit,
+        format!("SCAN returned {} records with limit {}", items.len(), limit)
+    )?;
+    assume!(
+        next.is_none() || !items.is_empty(),
+        "SCAN returned empty page which is not the last one"
+    )?;
+    let expected_items: Vec<(String, String)> = expected
+        .iter()
+        .filter(|(k, _)| k.as_str() >= start && k.as_str() < end)
+        .take(items.len())
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    assume_eq!(
+        items,
+        expected_items,
+        format!("Wrong SCAN results for [{}, {})", start, end)
+    )?;
+    if let Some(next) = &next {
+        assume!(
+            next.as_str() > items.last().unwrap().0.as_str() && next.as_str() <= end,
+            format!("Wrong next key {} for the page", next)
+        )?;
+    } else {
+        let remaining = expected
+            .iter()
+            .filter(|(k, _)| k.as_str() >= start && k.as_str() < end)
+            .count();
+        assume!(
+            remaining == items.len(),
+            format!(
+                "SCAN for [{}, {}) stopped before the end of range",
+                start, end
+            )
+        )?;
+    }
+    Ok(next)
+}
+
+// reads all records with keys in [start, end) page by page and compares them with expected
+fn check_scan(
+    sys: &mut System<JsonMessage>,
+    node: &str,
+    start: &str,
+    end: &str,
+    limit: usize,
+    expected: &HashMap<String, String>,
+) -> TestResult {
+    let mut expected_items: Vec<(&String, &String)> = expected.iter().collect();
+    expected_items.sort();
+    let mut next = Some(start.to_string());
+    while let Some(page_start) = next {
+        next = check_scan_page(sys, node, &page_start, end, limit, &expected_items)?;
+    }
+    Ok(true)
+}
+
+fn random_range(rand: &mut Pcg64) -> (String, String) {
+    let a = random_string(rand.gen_range(1..4), rand).to_uppercase();
+    let b = random_string(rand.gen_range(1..4), rand).to_uppercase();
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+// initial balance of accounts in transaction tests
+const INITIAL_BALANCE: i64 = 100;
+
+// bounds of the whole key space (keys consist of uppercase letters and digits)
+const MIN_KEY: &str = "";
+const MAX_KEY: &str = "~";
+
+const SYMBOLS: [char; 36] = [
+    'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's',
+    't', 'u', 'v', 'w', 'x', 'y', 'z', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9',
+];
+const WEIGHTS: [usize; 36] = [
+    13, 16, 3, 8, 8, 5, 6, 23, 4, 8, 24, 12, 2, 1, 1, 10, 5, 8, 10, 1, 24, 3, 1, 8, 12, 22, 5, 20,
+    18, 5, 5, 2, 1, 3, 16, 22,
+];
+
+fn random_string(length: usize, rand: &mut Pcg64) -> String {
+    let dist = WeightedIndex::new(&WEIGHTS).unwrap();
+    rand.sample_iter(&dist)
+        .take(length)
+        .map(|x| SYMBOLS[x])
+        .collect()
+}
+
+// TESTS ---------------------------------------------------------------------------------------------------------------
+
+fn test_single_node(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+
+    let node = "0";
+    let key = random_string(8, &mut rand).to_uppercase();
+    let value = random_string(8, &mut rand);
+    let max_steps = 10;
+
+    check_get(&mut sys, node, &key, None, max_steps)?;
+    check_put(&mut sys, node, &key, &value, max_steps)?;
+    check_get(&mut sys, node, &key, Some(&value), max_steps)?;
+    check_delete(&mut sys, node, &key, Some(&value), max_steps)?;
+    check_get(&mut sys, node, &key, None, max_steps)?;
+    check_delete(&mut sys, node, &key, None, max_steps)
+}
+
+fn test_inserts(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+
+    // insert random key-value pairs from each node
+    let mut kv = HashMap::new();
+    for node in sys.get_node_ids() {
+        let k = random_string(8, &mut rand).to_uppercase();
+        let v = random_string(8, &mut rand);
+        check_put(&mut sys, &node, &k, &v, 100)?;
+        kv.insert(k, v);
+    }
+
+    // check that all key-values can be read from each node
+    let nodes = sys.get_node_ids();
+    check(&mut sys, &nodes, &kv, true, false, config)
+}
+
+fn test_deletes(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut kv = HashMap::new();
+
+    // insert random key-value pairs from each node
+    for node in sys.get_node_ids() {
+        let k = random_string(8, &mut rand).to_uppercase();
+        let v = random_string(8, &mut rand);
+        check_put(&mut sys, &node, &k, &v, 100)?;
+        kv.insert(k, v);
+    }
+
+    // delete each key from one node and check that key is not present from another
+    for (k, v) in kv.iter() {
         let read_node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
         let mut delete_node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
         while delete_node == read_node {
@@ -468,7 +1342,7 @@ fn test_deletes(config: &TestConfig) -> TestResult {
 
     kv.clear();
     let nodes = sys.get_node_ids();
-    check(&mut sys, &nodes, &kv, false, false)
+    check(&mut sys, &nodes, &kv, false, false, config)
 }
 
 fn test_memory_overhead(config: &TestConfig) -> TestResult {
@@ -500,6 +1374,7 @@ fn test_memory_overhead(config: &TestConfig) -> TestResult {
 
 fn test_node_added(config: &TestConfig) -> TestResult {
     let mut sys = build_system(config, false);
+    let mut epoch = 0;
     let mut rand = Pcg64::seed_from_u64(config.seed);
 
     // insert random key-value pairs
@@ -516,17 +1391,18 @@ fn test_node_added(config: &TestConfig) -> TestResult {
     // add new node to the system
     let added = format!("{}", sys.get_node_ids().len());
     add_node(&added, &mut sys, config);
-    send_node_added(&mut sys, &added);
+    send_node_added(&mut sys, &added, &mut epoch, config);
 
     // run the system until key the distribution is stabilized
     let nodes = sys.get_node_ids();
-    step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000)?;
+    step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
 
-    check(&mut sys, &nodes, &kv, true, false)
+    check(&mut sys, &nodes, &kv, true, false, config)
 }
 
 fn test_node_removed(config: &TestConfig) -> TestResult {
     let mut sys = build_system(config, false);
+    let mut epoch = 0;
     let mut rand = Pcg64::seed_from_u64(config.seed);
 
     // insert random key-value pairs
@@ -544,7 +1420,7 @@ fn test_node_removed(config: &TestConfig) -> TestResult {
     let removed = sys.get_node_ids().choose(&mut rand).unwrap().clone();
     let count = count_records(&mut sys, &removed)?;
     assume!(count > 0, "Node stores no records, bad distribution")?;
-    send_node_removed(&mut sys, &removed);
+    send_node_removed(&mut sys, &removed, &mut epoch, config);
 
     // run the system until key the distribution is stabilized
     let nodes: Vec<String> = sys
@@ -552,13 +1428,14 @@ fn test_node_removed(config: &TestConfig) -> TestResult {
         .into_iter()
         .filter(|x| *x != removed)
         .collect();
-    step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000)?;
+    step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
 
-    check(&mut sys, &nodes, &kv, true, false)
+    check(&mut sys, &nodes, &kv, true, false, config)
 }
 
 fn test_node_removed_after_crash(config: &TestConfig) -> TestResult {
     let mut sys = build_system(config, false);
+    let mut epoch = 0;
     let mut rand = Pcg64::seed_from_u64(config.seed);
 
     // insert random key-value pairs
@@ -583,7 +1460,7 @@ fn test_node_removed_after_crash(config: &TestConfig) -> TestResult {
         kv.remove(&k);
     }
     sys.crash_node(&crashed);
-    send_node_removed(&mut sys, &crashed);
+    send_node_removed(&mut sys, &crashed, &mut epoch, config);
 
     // run the system until key the distribution is stabilized
     let nodes: Vec<String> = sys
@@ -591,13 +1468,14 @@ fn test_node_removed_after_crash(config: &TestConfig) -> TestResult {
         .into_iter()
         .filter(|x| *x != crashed)
         .collect();
-    step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000)?;
+    step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
 
-    check(&mut sys, &nodes, &kv, true, false)
+    check(&mut sys, &nodes, &kv, true, false, config)
 }
 
 fn test_migration(config: &TestConfig) -> TestResult {
     let mut sys = build_system(config, false);
+    let mut epoch = 0;
     let mut rand = Pcg64::seed_from_u64(config.seed);
     let mut nodes = sys.get_node_ids().clone();
 
@@ -616,26 +1494,27 @@ fn test_migration(config: &TestConfig) -> TestResult {
     for i in 0..config.node_count {
         let added = format!("{}", config.node_count + i);
         add_node(&added, &mut sys, config);
-        send_node_added(&mut sys, &added);
+        send_node_added(&mut sys, &added, &mut epoch, config);
         nodes.push(added);
-        step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000)?;
+        step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
     }
 
-    check(&mut sys, &nodes, &kv, false, false)?;
+    check(&mut sys, &nodes, &kv, false, false, config)?;
 
     // remove old N nodes
     for i in 0..config.node_count {
         let removed = format!("{}", i);
-        send_node_removed(&mut sys, &removed);
+        send_node_removed(&mut sys, &removed, &mut epoch, config);
         nodes.remove(0);
-        step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000)?;
+        step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
     }
 
-    check(&mut sys, &nodes, &kv, false, false)
+    check(&mut sys, &nodes, &kv, false, false, config)
 }
 
 fn test_scale_up_down(config: &TestConfig) -> TestResult {
     let mut sys = build_system(config, false);
+    let mut epoch = 0;
     let mut rand = Pcg64::seed_from_u64(config.seed);
     let mut nodes = sys.get_node_ids().clone();
 
@@ -654,22 +1533,196 @@ fn test_scale_up_down(config: &TestConfig) -> TestResult {
     for i in 0..config.node_count {
         let added = format!("{}", config.node_count + i);
         add_node(&added, &mut sys, config);
-        send_node_added(&mut sys, &added);
+        send_node_added(&mut sys, &added, &mut epoch, config);
         nodes.push(added);
-        step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000)?;
+        step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
     }
 
-    check(&mut sys, &nodes, &kv, false, false)?;
+    check(&mut sys, &nodes, &kv, false, false, config)?;
+    check_routing(&mut sys, &nodes, &mut kv, 100, &mut rand)?;
 
     // remove new N nodes
     for i in 0..config.node_count {
         let removed = format!("{}", config.node_count + i);
-        send_node_removed(&mut sys, &removed);
+        send_node_removed(&mut sys, &removed, &mut epoch, config);
         nodes.remove(config.node_count as usize);
-        step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000)?;
+        step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
+    }
+
+    check(&mut sys, &nodes, &kv, false, false, config)?;
+    check_routing(&mut sys, &nodes, &mut kv, 100, &mut rand)
+}
+
+fn test_scale_out_multiple(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut epoch = 0;
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut kv = HashMap::new();
+
+    // insert random key-value pairs
+    let keys_count = 10000;
+    for _ in 0..keys_count {
+        let k = random_string(8, &mut rand).to_uppercase();
+        let v = random_string(8, &mut rand);
+        let node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+        check_put(&mut sys, &node, &k, &v, 100)?;
+        kv.insert(k, v);
+    }
+    let dist_before = key_distribution(&mut sys)?;
+
+    // add N/2 new nodes at once without waiting for rebalancing
+    let mut added = Vec::new();
+    for i in 0..(config.node_count / 2).max(1) {
+        let node = format!("{}", config.node_count + i);
+        add_node(&node, &mut sys, config);
+        send_node_added(&mut sys, &node, &mut epoch, config);
+        added.push(node);
+    }
+
+    // run the system until key the distribution is stabilized
+    let nodes = sys.get_node_ids();
+    step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
+    let dist_after = key_distribution(&mut sys)?;
+
+    let target_moved_keys = added
+        .iter()
+        .map(|node| keys_count as f64 * weight_share(node, &nodes, config))
+        .sum::<f64>()
+        .round() as u64;
+    check_moved_keys(&mut sys, &dist_before, &dist_after, target_moved_keys)?;
+
+    check(&mut sys, &nodes, &kv, false, true, config)
+}
+
+fn test_scale_in_multiple(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut epoch = 0;
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut kv = HashMap::new();
+
+    // insert random key-value pairs
+    let keys_count = 10000;
+    for _ in 0..keys_count {
+        let k = random_string(8, &mut rand).to_uppercase();
+        let v = random_string(8, &mut rand);
+        let node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+        check_put(&mut sys, &node, &k, &v, 100)?;
+        kv.insert(k, v);
+    }
+    let dist_before = key_distribution(&mut sys)?;
+
+    // remove N/2 nodes at once without waiting for rebalancing
+    let all_nodes = sys.get_node_ids();
+    let removed: Vec<String> = all_nodes
+        .choose_multiple(&mut rand, (config.node_count / 2) as usize)
+        .cloned()
+        .collect();
+    for node in removed.iter() {
+        send_node_removed(&mut sys, node, &mut epoch, config);
+    }
+
+    // run the system until key the distribution is stabilized
+    let nodes: Vec<String> = all_nodes
+        .iter()
+        .filter(|x| !removed.contains(x))
+        .cloned()
+        .collect();
+    step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
+    let dist_after = key_distribution(&mut sys)?;
+
+    let target_moved_keys = removed
+        .iter()
+        .map(|node| keys_count as f64 * weight_share(node, &all_nodes, config))
+        .sum::<f64>()
+        .round() as u64;
+    check_moved_keys(&mut sys, &dist_before, &dist_after, target_moved_keys)?;
+
+    check(&mut sys, &nodes, &kv, false, true, config)
+}
+
+fn test_scale_out_in_multiple(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut epoch = 0;
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut kv = HashMap::new();
+
+    // insert random key-value pairs
+    let keys_count = 10000;
+    for _ in 0..keys_count {
+        let k = random_string(8, &mut rand).to_uppercase();
+        let v = random_string(8, &mut rand);
+        let node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+        check_put(&mut sys, &node, &k, &v, 100)?;
+        kv.insert(k, v);
+    }
+    let dist_before = key_distribution(&mut sys)?;
+    let old_nodes = sys.get_node_ids();
+
+    // add and remove two nodes (or less if there are fewer nodes) interleaved
+    // without waiting for rebalancing, also add a node and immediately remove it
+    let change_count = old_nodes.len().min(2);
+    let removed: Vec<String> = old_nodes
+        .choose_multiple(&mut rand, change_count)
+        .cloned()
+        .collect();
+    let added: Vec<String> = (0..change_count as u32)
+        .map(|i| format!("{}", config.node_count + i))
+        .collect();
+    for i in 0..change_count {
+        add_node(&added[i], &mut sys, config);
+        send_node_added(&mut sys, &added[i], &mut epoch, config);
+        send_node_removed(&mut sys, &removed[i], &mut epoch, config);
+    }
+    let transient = format!("{}", config.node_count + change_count as u32);
+    add_node(&transient, &mut sys, config);
+    send_node_added(&mut sys, &transient, &mut epoch, config);
+    send_node_removed(&mut sys, &transient, &mut epoch, config);
+
+    // run the system until key the distribution is stabilized
+    let nodes: Vec<String> = sys
+        .get_node_ids()
+        .into_iter()
+        .filter(|x| !removed.contains(x) && *x != transient)
+        .collect();
+    step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
+    let dist_after = key_distribution(&mut sys)?;
+
+    // keys stored on removed nodes and keys which end up on added nodes must move
+    // (keys from removed nodes which end up on added nodes are counted once),
+    // the transient node should not cause additional moves
+    let removed_share: f64 = removed
+        .iter()
+        .map(|node| weight_share(node, &old_nodes, config))
+        .sum();
+    let added_share: f64 = added
+        .iter()
+        .map(|node| weight_share(node, &nodes, config))
+        .sum();
+    let target_moved_keys =
+        (keys_count as f64 * (removed_share + added_share * (1. - removed_share))).round() as u64;
+    check_moved_keys(&mut sys, &dist_before, &dist_after, target_moved_keys)?;
+
+    check(&mut sys, &nodes, &kv, false, true, config)
+}
+
+fn test_routing(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+
+    // insert random key-value pairs
+    let keys_count = 100;
+    let mut kv = HashMap::new();
+    for _ in 0..keys_count {
+        let k = random_string(8, &mut rand).to_uppercase();
+        let v = random_string(8, &mut rand);
+        let node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+        check_put(&mut sys, &node, &k, &v, 100)?;
+        kv.insert(k, v);
     }
 
-    check(&mut sys, &nodes, &kv, false, false)
+    // measure routing in stable system
+    let nodes = sys.get_node_ids();
+    check_routing(&mut sys, &nodes, &mut kv, 500, &mut rand)
 }
 
 fn test_distribution(config: &TestConfig) -> TestResult {
@@ -688,11 +1741,12 @@ fn test_distribution(config: &TestConfig) -> TestResult {
     }
 
     let nodes = sys.get_node_ids();
-    check(&mut sys, &nodes, &kv, false, true)
+    check(&mut sys, &nodes, &kv, false, true, config)
 }
 
 fn test_distribution_node_added(config: &TestConfig) -> TestResult {
     let mut sys = build_system(config, false);
+    let mut epoch = 0;
     let mut rand = Pcg64::seed_from_u64(config.seed);
     let mut kv = HashMap::new();
 
@@ -710,21 +1764,35 @@ fn test_distribution_node_added(config: &TestConfig) -> TestResult {
     // add new node to the system
     let added = format!("{}", sys.get_node_ids().len());
     add_node(&added, &mut sys, config);
-    send_node_added(&mut sys, &added);
+    let init_msg_count = sys.get_network_message_count();
+    let init_net_traffic = sys.get_network_traffic();
+    send_node_added(&mut sys, &added, &mut epoch, config);
 
     // run the system until key the distribution is stabilized
     let nodes = sys.get_node_ids();
-    step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000)?;
+    step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
+    let message_count = sys.get_network_message_count() - init_msg_count;
+    let traffic = sys.get_network_traffic() - init_net_traffic;
     let dist_after = key_distribution(&mut sys)?;
 
-    let target_moved_keys = (keys_count as f64 / nodes.len() as f64).round() as u64;
+    let target_moved_keys =
+        (keys_count as f64 * weight_share(&added, &nodes, config)).round() as u64;
     check_moved_keys(&mut sys, &dist_before, &dist_after, target_moved_keys)?;
+    check_rebalance_cost(
+        &dist_before,
+        &dist_after,
+        &kv,
+        message_count,
+        traffic,
+        config,
+    )?;
 
-    check(&mut sys, &nodes, &kv, false, true)
+    check(&mut sys, &nodes, &kv, false, true, config)
 }
 
 fn test_distribution_node_removed(config: &TestConfig) -> TestResult {
     let mut sys = build_system(config, false);
+    let mut epoch = 0;
     let mut rand = Pcg64::seed_from_u64(config.seed);
     let mut kv = HashMap::new();
 
@@ -743,7 +1811,9 @@ fn test_distribution_node_removed(config: &TestConfig) -> TestResult {
     let removed = sys.get_node_ids().choose(&mut rand).unwrap().clone();
     let count = count_records(&mut sys, &removed)?;
     assume!(count > 0, "Node stores no records, bad distribution")?;
-    send_node_removed(&mut sys, &removed);
+    let init_msg_count = sys.get_network_message_count();
+    let init_net_traffic = sys.get_network_traffic();
+    send_node_removed(&mut sys, &removed, &mut epoch, config);
 
     // run the system until key the distribution is stabilized
     let nodes: Vec<String> = sys
@@ -751,13 +1821,481 @@ fn test_distribution_node_removed(config: &TestConfig) -> TestResult {
         .into_iter()
         .filter(|x| *x != removed)
         .collect();
-    step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000)?;
+    step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
+    let message_count = sys.get_network_message_count() - init_msg_count;
+    let traffic = sys.get_network_traffic() - init_net_traffic;
     let dist_after = key_distribution(&mut sys)?;
 
-    let target_moved_keys = (keys_count as f64 / (nodes.len() + 1) as f64).round() as u64;
+    let target_moved_keys =
+        (keys_count as f64 * weight_share(&removed, &sys.get_node_ids(), config)).round() as u64;
     check_moved_keys(&mut sys, &dist_before, &dist_after, target_moved_keys)?;
+    check_rebalance_cost(
+        &dist_before,
+        &dist_after,
+        &kv,
+        message_count,
+        traffic,
+        config,
+    )?;
+
+    check(&mut sys, &nodes, &kv, false, true, config)
+}
+
+fn test_load_balance_zipf(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+
+    // insert random key-value pairs
+    let keys_count = 1000;
+    let mut kv = HashMap::new();
+    let mut keys = Vec::new();
+    for _ in 0..keys_count {
+        let k = random_string(8, &mut rand).to_uppercase();
+        let v = random_string(8, &mut rand);
+        let node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+        check_put(&mut sys, &node, &k, &v, 100)?;
+        if kv.insert(k.clone(), v).is_none() {
+            keys.push(k);
+        }
+    }
+    let owners = key_owners(&mut sys)?;
+    let nodes = sys.get_node_ids();
+
+    // read and update keys from random nodes with Zipf-distributed key popularity
+    let popularity = zipf_distribution(keys.len(), config.zipf_exponent);
+    let init_received: HashMap<String, u64> = nodes
+        .iter()
+        .map(|node| (node.clone(), sys.get_received_message_count(node)))
+        .collect();
+    let mut owner_requests: HashMap<String, u64> =
+        nodes.iter().map(|node| (node.clone(), 0)).collect();
+    for _ in 0..5000 {
+        let k = &keys[popularity.sample(&mut rand)];
+        let node = nodes.choose(&mut rand).unwrap();
+        if rand.gen_bool(0.8) {
+            check_get(&mut sys, node, k, Some(&kv[k]), 100)?;
+        } else {
+            let v = random_string(8, &mut rand);
+            check_put(&mut sys, node, k, &v, 100)?;
+            kv.insert(k.clone(), v);
+        }
+        *owner_requests.get_mut(&owners[k]).unwrap() += 1;
+    }
+    let received: HashMap<String, u64> = nodes
+        .iter()
+        .map(|node| {
+            let count = sys.get_received_message_count(node) - init_received[node];
+            (node.clone(), count)
+        })
+        .collect();
+
+    report_load("Requests to key owner", &owner_requests);
+    let load_ratio = report_load("Received messages", &received);
+    let hottest = &keys[0];
+    println!("\nHottest key {} is stored on {}", hottest, owners[hottest]);
+    if let Some(max_ratio) = config.max_load_ratio {
+        assume!(
+            load_ratio <= max_ratio,
+            format!(
+                "Max node load is {:.2} times above average (hot keys are not split or replicated)",
+                load_ratio
+            )
+        )?;
+    }
 
-    check(&mut sys, &nodes, &kv, false, true)
+    Ok(true)
+}
+
+fn test_online_node_added(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut epoch = 0;
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+
+    // insert random key-value pairs
+    let keys_count = 100;
+    let mut kv = HashMap::new();
+    for _ in 0..keys_count {
+        let k = random_string(8, &mut rand).to_uppercase();
+        let v = random_string(8, &mut rand);
+        let node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+        check_put(&mut sys, &node, &k, &v, 100)?;
+        kv.insert(k, v);
+    }
+
+    // add new node to the system and keep sending requests during rebalancing
+    let added = format!("{}", sys.get_node_ids().len());
+    add_node(&added, &mut sys, config);
+    send_node_added(&mut sys, &added, &mut epoch, config);
+    let nodes = sys.get_node_ids();
+    check_random_ops(&mut sys, &nodes, &mut kv, 100, &mut rand)?;
+
+    // run the system until key the distribution is stabilized
+    step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
+
+    check(&mut sys, &nodes, &kv, true, false, config)
+}
+
+fn test_online_node_removed(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut epoch = 0;
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+
+    // insert random key-value pairs
+    let keys_count = 100;
+    let mut kv = HashMap::new();
+    for _ in 0..keys_count {
+        let k = random_string(8, &mut rand).to_uppercase();
+        let v = random_string(8, &mut rand);
+        let node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+        check_put(&mut sys, &node, &k, &v, 100)?;
+        kv.insert(k, v);
+    }
+
+    // remove a node from the system and keep sending requests during rebalancing
+    let removed = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+    let count = count_records(&mut sys, &removed)?;
+    assume!(count > 0, "Node stores no records, bad distribution")?;
+    send_node_removed(&mut sys, &removed, &mut epoch, config);
+    let nodes: Vec<String> = sys
+        .get_node_ids()
+        .into_iter()
+        .filter(|x| *x != removed)
+        .collect();
+    check_random_ops(&mut sys, &nodes, &mut kv, 100, &mut rand)?;
+
+    // run the system until key the distribution is stabilized
+    step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
+
+    check(&mut sys, &nodes, &kv, true, false, config)
+}
+
+fn test_online_migration(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut epoch = 0;
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut nodes = sys.get_node_ids().clone();
+
+    // insert random key-value pairs
+    let keys_count = 1000;
+    let mut kv = HashMap::new();
+    for _ in 0..keys_count {
+        let k = random_string(8, &mut rand).to_uppercase();
+        let v = random_string(8, &mut rand);
+        let node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+        check_put(&mut sys, &node, &k, &v, 100)?;
+        kv.insert(k, v);
+    }
+
+    // add new N nodes to the system while sending requests
+    for i in 0..config.node_count {
+        let added = format!("{}", config.node_count + i);
+        add_node(&added, &mut sys, config);
+        send_node_added(&mut sys, &added, &mut epoch, config);
+        nodes.push(added);
+        check_random_ops(&mut sys, &nodes, &mut kv, 50, &mut rand)?;
+        step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
+    }
+
+    check(&mut sys, &nodes, &kv, false, false, config)?;
+
+    // remove old N nodes while sending requests
+    for i in 0..config.node_count {
+        let removed = format!("{}", i);
+        send_node_removed(&mut sys, &removed, &mut epoch, config);
+        nodes.remove(0);
+        check_random_ops(&mut sys, &nodes, &mut kv, 50, &mut rand)?;
+        step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
+    }
+
+    check(&mut sys, &nodes, &kv, true, false, config)
+}
+
+fn test_batch_ops(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut kv: HashMap<String, String> = HashMap::new();
+
+    // insert random key-value pairs in batches from random nodes
+    let mut mput_batches = Vec::new();
+    for _ in 0..50 {
+        let batch_size = rand.gen_range(1..50);
+        let mut items = HashMap::new();
+        for _ in 0..batch_size {
+            let k = if rand.gen_bool(0.1) && !kv.is_empty() {
+                sorted_keys(&kv).choose(&mut rand).unwrap().clone()
+            } else {
+                random_string(8, &mut rand).to_uppercase()
+            };
+            let v = random_string(8, &mut rand);
+            items.insert(k, v);
+        }
+        let node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+        let init_msg_count = sys.get_network_message_count();
+        check_mput(&mut sys, &node, &items)?;
+        let message_count = sys.get_network_message_count() - init_msg_count;
+        mput_batches.push((items.keys().cloned().collect(), message_count));
+        kv.extend(items);
+    }
+
+    // read random existing and missing keys in batches from random nodes
+    let mut mget_batches = Vec::new();
+    for _ in 0..50 {
+        let batch_size = rand.gen_range(1..50);
+        let mut keys: Vec<String> = sorted_keys(&kv)
+            .choose_multiple(&mut rand, batch_size)
+            .cloned()
+            .collect();
+        keys.push(random_string(8, &mut rand).to_uppercase());
+        let node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+        let init_msg_count = sys.get_network_message_count();
+        check_mget(&mut sys, &node, &keys, &kv)?;
+        let message_count = sys.get_network_message_count() - init_msg_count;
+        mget_batches.push((keys, message_count));
+    }
+
+    // check that batch operations are consistent with single-key ones
+    let nodes = sys.get_node_ids();
+    check(&mut sys, &nodes, &kv, false, false, config)?;
+    for k in sorted_keys(&kv).choose_multiple(&mut rand, 20) {
+        let node = nodes.choose(&mut rand).unwrap();
+        check_get(&mut sys, node, k, kv.get(k).map(|v| v.as_str()), 100)?;
+    }
+
+    let dist = key_distribution(&mut sys)?;
+    println!("\nMPUT:");
+    check_batch_messages(&mput_batches, &dist)?;
+    println!("\nMGET:");
+    check_batch_messages(&mget_batches, &dist)
+}
+
+fn test_transactions(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut balances = create_accounts(&mut sys, 20, None, &mut rand)?;
+    let owners = key_owners(&mut sys)?;
+    let mut accounts: Vec<String> = balances.keys().cloned().collect();
+    accounts.sort();
+    let nodes = sys.get_node_ids();
+
+    // concurrent transfers from different nodes along with read-only transactions
+    let mut committed = 0;
+    for _ in 0..20 {
+        let coordinators: Vec<String> = nodes.choose_multiple(&mut rand, 4).cloned().collect();
+        for node in coordinators.iter().skip(1) {
+            let (from, to) = random_transfer(&owners, &mut rand)?;
+            let amount = rand.gen_range(1..150);
+            send_transfer(&mut sys, node, &from, &to, amount);
+        }
+        check_total_balance(&mut sys, &coordinators[0], &accounts, 1000)?;
+        for node in coordinators.iter().skip(1) {
+            if check_transfer_resp(&mut sys, node, &mut balances, 1000)? {
+                committed += 1;
+            }
+        }
+        // all transfers are completed, so balances must match exactly
+        let reader = nodes.choose(&mut rand).unwrap();
+        let observed = check_total_balance(&mut sys, reader, &accounts, 1000)?;
+        assume_eq!(
+            observed,
+            balances,
+            "Balances do not match committed transfers"
+        )?;
+    }
+    println!("Committed transfers: {} of {}", committed, 20 * 3);
+    assume!(committed > 0, "No transfer is committed")?;
+
+    // balances are also visible to single-key reads
+    for (k, v) in balances.iter() {
+        let node = nodes.choose(&mut rand).unwrap();
+        check_get(&mut sys, node, k, Some(&v.to_string()), 100)?;
+    }
+    Ok(true)
+}
+
+fn test_transactions_coordinator_crash(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut epoch = 0;
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let coordinator = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+    let balances = create_accounts(&mut sys, 40, Some(&coordinator), &mut rand)?;
+    let owners = key_owners(&mut sys)?;
+    let mut accounts: Vec<String> = balances.keys().cloned().collect();
+    accounts.sort();
+
+    // coordinator crashes in the middle of transfers between accounts stored on other nodes
+    for _ in 0..5 {
+        let (from, to) = random_transfer(&owners, &mut rand)?;
+        let amount = rand.gen_range(1..50);
+        send_transfer(&mut sys, &coordinator, &from, &to, amount);
+    }
+    sys.steps(rand.gen_range(5..30));
+    sys.crash_node(&coordinator);
+    send_node_removed(&mut sys, &coordinator, &mut epoch, config);
+
+    // run the system until key the distribution is stabilized
+    let nodes: Vec<String> = sys
+        .get_node_ids()
+        .into_iter()
+        .filter(|x| *x != coordinator)
+        .collect();
+    step_until_stabilized(&mut sys, &nodes, accounts.len() as u64, 100, 1000, config)?;
+
+    // each transfer is either fully applied or not applied at all
+    let reader = nodes.choose(&mut rand).unwrap().clone();
+    let mut balances = check_total_balance(&mut sys, &reader, &accounts, 1000)?;
+
+    // accounts are not left locked by unfinished transfers
+    // (owners are changed after the coordinator keys are moved)
+    let owners = key_owners(&mut sys)?;
+    let from = accounts
+        .iter()
+        .max_by_key(|k| balances[*k])
+        .unwrap()
+        .clone();
+    let to = accounts
+        .iter()
+        .find(|k| owners[*k] != owners[&from])
+        .ok_or("All accounts are stored on a single node, cross-shard transfer is impossible")?
+        .clone();
+    let node = nodes.choose(&mut rand).unwrap().clone();
+    send_transfer(&mut sys, &node, &from, &to, 1);
+    let res = check_transfer_resp(&mut sys, &node, &mut balances, 1000)?;
+    assume!(res, "Transfer after coordinator crash is not committed")?;
+    let observed = check_total_balance(&mut sys, &reader, &accounts, 1000)?;
+    assume_eq!(
+        observed,
+        balances,
+        "Balances do not match committed transfers"
+    )?;
+    Ok(true)
+}
+
+fn test_transactions_participant_disconnect(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut balances = create_accounts(&mut sys, 20, None, &mut rand)?;
+    let owners = key_owners(&mut sys)?;
+    let mut accounts: Vec<String> = balances.keys().cloned().collect();
+    accounts.sort();
+    let nodes = sys.get_node_ids();
+
+    // participant storing some accounts is disconnected in the middle of transfers
+    let mut participants: Vec<&String> = owners
+        .values()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    participants.sort();
+    let participant = participants.choose(&mut rand).unwrap().to_string();
+    let coordinators: Vec<String> = nodes
+        .iter()
+        .filter(|x| **x != participant)
+        .cloned()
+        .choose_multiple(&mut rand, 4);
+    for node in coordinators.iter() {
+        let (from, to) = random_transfer(&owners, &mut rand)?;
+        let amount = rand.gen_range(1..50);
+        send_transfer(&mut sys, node, &from, &to, amount);
+    }
+    sys.steps(rand.gen_range(5..30));
+    sys.disconnect_node(&participant);
+    sys.steps(200);
+    sys.connect_node(&participant);
+
+    // all transfers are completed (committed or aborted) after the participant is back
+    for node in coordinators.iter() {
+        check_transfer_resp(&mut sys, node, &mut balances, 10000)?;
+    }
+    let reader = nodes.choose(&mut rand).unwrap();
+    let observed = check_total_balance(&mut sys, reader, &accounts, 1000)?;
+    assume_eq!(
+        observed,
+        balances,
+        "Balances do not match committed transfers"
+    )?;
+    Ok(true)
+}
+
+fn test_scan(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+
+    // insert random key-value pairs
+    let keys_count = 1000;
+    let mut kv = HashMap::new();
+    for _ in 0..keys_count {
+        let k = random_string(8, &mut rand).to_uppercase();
+        let v = random_string(8, &mut rand);
+        let node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+        check_put(&mut sys, &node, &k, &v, 100)?;
+        kv.insert(k, v);
+    }
+
+    // scan the whole key space and random ranges from random nodes
+    let node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+    check_scan(&mut sys, &node, MIN_KEY, MAX_KEY, 100, &kv)?;
+    for _ in 0..20 {
+        let node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+        let (start, end) = random_range(&mut rand);
+        let limit = rand.gen_range(1..50);
+        check_scan(&mut sys, &node, &start, &end, limit, &kv)?;
+    }
+
+    // empty range
+    let node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+    check_scan(&mut sys, &node, "B", "B", 10, &kv)
+}
+
+fn test_scan_node_added(config: &TestConfig) -> TestResult {
+    let mut sys = build_system(config, false);
+    let mut epoch = 0;
+    let mut rand = Pcg64::seed_from_u64(config.seed);
+    let mut nodes = sys.get_node_ids().clone();
+
+    // insert random key-value pairs
+    let keys_count = 1000;
+    let mut kv = HashMap::new();
+    for _ in 0..keys_count {
+        let k = random_string(8, &mut rand).to_uppercase();
+        let v = random_string(8, &mut rand);
+        let node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+        check_put(&mut sys, &node, &k, &v, 100)?;
+        kv.insert(k, v);
+    }
+    let mut expected_items: Vec<(&String, &String)> = kv.iter().collect();
+    expected_items.sort();
+
+    // scan the whole key space while adding new nodes between pages
+    let node = sys.get_node_ids().choose(&mut rand).unwrap().clone();
+    let mut next = Some(MIN_KEY.to_string());
+    let mut page = 0;
+    while let Some(page_start) = next {
+        next = check_scan_page(&mut sys, &node, &page_start, MAX_KEY, 50, &expected_items)?;
+        page += 1;
+        if page % 4 == 0 && nodes.len() < 2 * config.node_count as usize {
+            let added = format!("{}", nodes.len());
+            add_node(&added, &mut sys, config);
+            send_node_added(&mut sys, &added, &mut epoch, config);
+            nodes.push(added);
+            step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
+        }
+    }
+
+    // scan the whole key space from the added node
+    let node = nodes.last().unwrap().clone();
+    check_scan(&mut sys, &node, MIN_KEY, MAX_KEY, 100, &kv)?;
+
+    // remove a node and scan random ranges
+    let removed = nodes.remove(rand.gen_range(0..nodes.len()));
+    send_node_removed(&mut sys, &removed, &mut epoch, config);
+    step_until_stabilized(&mut sys, &nodes, kv.len() as u64, 100, 1000, config)?;
+    for _ in 0..10 {
+        let node = nodes.choose(&mut rand).unwrap().clone();
+        let (start, end) = random_range(&mut rand);
+        let limit = rand.gen_range(1..50);
+        check_scan(&mut sys, &node, &start, &end, limit, &kv)?;
+    }
+    Ok(true)
 }
 
 // CLI -----------------------------------------------------------------------------------------------------------------
@@ -786,6 +2324,50 @@ struct Args {
     #[clap(long, short, default_value = "123")]
     seed: u64,
 
+    /// Assign different weights to nodes and expect keys distribution proportional to them
+    #[clap(long)]
+    weighted: bool,
+
+    /// Max ratio of rebalancing messages to the minimal number of messages needed to move keys (optional)
+    #[clap(long)]
+    max_rebalance_messages: Option<f64>,
+
+    /// Max ratio of rebalancing traffic to the minimal traffic needed to move keys
+    #[clap(long, default_value = "1.5")]
+    max_rebalance_traffic: f64,
+
+    /// Exponent of Zipf distribution of key popularity in load balance test
+    #[clap(long, default_value = "1.0")]
+    zipf_exponent: f64,
+
+    /// Max ratio of max node load to average node load on skewed workload (optional)
+    #[clap(long)]
+    max_load_ratio: Option<f64>,
+
+    /// Enable tests of MGET and MPUT operations
+    #[clap(long)]
+    batch: bool,
+
+    /// Enable tests of cross-shard transactions (TRANSFER and TX_READ operations)
+    #[clap(long)]
+    transactions: bool,
+
+    /// Enable tests of SCAN operation
+    #[clap(long)]
+    scan: bool,
+
+    /// Wait for REBALANCE_DONE responses to NODE_ADDED/NODE_REMOVED instead of polling record counts
+    #[clap(long)]
+    rebalance_done: bool,
+
+    /// Enable tests with requests sent during rebalancing
+    #[clap(long)]
+    online: bool,
+
+    /// Enable test of load distribution on skewed (Zipf) workload
+    #[clap(long)]
+    load_balance: bool,
+
     /// Path to dslib directory
     #[clap(long = "lib", short = 'l', default_value = "../../dslib")]
     dslib_path: String,
@@ -805,6 +2387,12 @@ fn main() {
         node_factory: &node_factory,
         node_count: args.node_count,
         seed: args.seed,
+        weighted: args.weighted,
+        max_rebalance_messages: args.max_rebalance_messages,
+        max_rebalance_traffic: args.max_rebalance_traffic,
+        zipf_exponent: args.zipf_exponent,
+        max_load_ratio: args.max_load_ratio,
+        rebalance_done: args.rebalance_done,
     };
     let mut single_config = config.clone();
     single_config.node_count = 1;
@@ -823,6 +2411,7 @@ fn main() {
     );
     tests.add("MIGRATION", test_migration, config);
     tests.add("SCALE UP DOWN", test_scale_up_down, config);
+    tests.add("ROUTING", test_routing, config);
     tests.add("DISTRIBUTION", test_distribution, config);
     tests.add(
         "DISTRIBUTION NODE ADDED",
@@ -834,6 +2423,37 @@ fn main() {
         test_distribution_node_removed,
         config,
     );
+    tests.add("SCALE OUT MULTIPLE", test_scale_out_multiple, config);
+    tests.add("SCALE IN MULTIPLE", test_scale_in_multiple, config);
+    tests.add("SCALE OUT IN MULTIPLE", test_scale_out_in_multiple, config);
+    if args.load_balance {
+        tests.add("LOAD BALANCE ZIPF", test_load_balance_zipf, config);
+    }
+    if args.batch {
+        tests.add("BATCH OPS", test_batch_ops, config);
+    }
+    if args.transactions {
+        tests.add("TRANSACTIONS", test_transactions, config);
+        tests.add(
+            "TRANSACTIONS COORDINATOR CRASH",
+            test_transactions_coordinator_crash,
+            config,
+        );
+        tests.add(
+            "TRANSACTIONS PARTICIPANT DISCONNECT",
+            test_transactions_participant_disconnect,
+            config,
+        );
+    }
+    if args.scan {
+        tests.add("SCAN", test_scan, config);
+        tests.add("SCAN NODE ADDED", test_scan_node_added, config);
+    }
+    if args.online {
+        tests.add("ONLINE NODE ADDED", test_online_node_added, config);
+        tests.add("ONLINE NODE REMOVED", test_online_node_removed, config);
+        tests.add("ONLINE MIGRATION", test_online_migration, config);
+    }
 
     if args.test.is_none() {
         tests.run();